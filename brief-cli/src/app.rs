@@ -1,92 +1,610 @@
 use std::{
+    collections::HashMap,
+    env,
     fs::{self, File},
-    io::{BufRead, BufReader},
-    path::Path,
+    io::{self, Write},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
     process::ExitCode,
 };
 
-use crate::{config::Config, table::Table};
+use zbus::zvariant::Value;
 
-pub fn list(config: &Config) -> ExitCode {
-    let Some(ref nix_profile) = config.nix_profile else {
+use crate::{
+    config::{host_data_home, Config, BOX_NAME},
+    menu, mime,
+    output::{Listing, Output},
+    util::KeyFile,
+};
+
+const DESKTOP_ENTRY: &str = "Desktop Entry";
+const DBUS_SERVICE_MARKER: &str = "# Exported from nixbox by brief. Do not edit.";
+
+pub fn list(config: &Config, output: &Output) -> ExitCode {
+    let Some(apps) = find_all(config) else {
         eprintln!("Nix not installed");
         return ExitCode::FAILURE;
     };
+    if apps.is_empty() {
+        eprintln!("No applications found");
+        return ExitCode::FAILURE;
+    }
+
+    let mut listing = Listing::new(&[
+        ("id", "ID"),
+        ("name", "NAME"),
+        ("exec", "COMMAND"),
+        ("comment", "COMMENT"),
+        ("source", ""),
+    ]);
+    listing.truncate(&["exec", "comment"]);
+    for desktop in apps {
+        listing.add_row(vec![
+            desktop.id.into(),
+            desktop.name.into(),
+            desktop.exec.into(),
+            desktop.comment.into(),
+            desktop.path.display().to_string().into(),
+        ])
+    }
+    listing.print(output)
+}
+
+pub fn install(config: &Config, ids: &[String], default_for: &[String]) -> ExitCode {
+    let Some(apps) = find_all(config) else {
+        eprintln!("Nix not installed");
+        return ExitCode::FAILURE;
+    };
+
+    let mut code = ExitCode::SUCCESS;
+    for id in ids {
+        let Some(app) = apps.iter().find(|app| &app.id == id) else {
+            eprintln!("No application with ID '{}' in the box", id);
+            code = ExitCode::FAILURE;
+            continue;
+        };
+
+        let path = export(config, app)
+            .unwrap_or_else(|err| panic!("Could not export application '{}': {}", id, err));
+        println!("Exported {} to {}", id, path.display());
+    }
+
+    for mime_type in default_for {
+        let Some(app) = ids
+            .iter()
+            .filter_map(|id| apps.iter().find(|app| &app.id == id))
+            .find(|app| app.mime_types.contains(mime_type))
+        else {
+            eprintln!("None of the applications can open '{}'", mime_type);
+            code = ExitCode::FAILURE;
+            continue;
+        };
+
+        mime::set_default(mime_type, &Exported::file_name(&app.id)).unwrap_or_else(|err| {
+            panic!(
+                "Could not set default application for '{}': {}",
+                mime_type, err
+            )
+        });
+        println!("Set {} as the default for {}", app.id, mime_type);
+    }
+
+    update_integration(config);
+    code
+}
+
+/// Make the host aware of the MIME types handled by exported applications,
+/// and group them in the host's application menu
+pub fn update_integration(config: &Config) {
+    let result = if Exported::find_all().is_empty() {
+        menu::uninstall()
+    } else {
+        menu::install()
+    };
+    if let Err(err) = result {
+        eprintln!("Could not update the application menu: {}", err);
+    }
 
-    let appdir = config
+    let appdir = host_applications_dir();
+    if appdir.is_dir() {
+        if let Err(err) = mime::update_cache(&appdir) {
+            eprintln!("Could not update mimeinfo.cache: {}", err);
+        }
+    }
+    if let Err(err) = mime::import_definitions(config) {
+        eprintln!("Could not import MIME definitions: {}", err);
+    }
+}
+
+/// Applications in the box's profile, ordered by ID
+pub fn find_all(config: &Config) -> Option<Vec<DesktopFile>> {
+    let nix_profile = config.nix_profile.as_ref()?;
+
+    let Ok(appdir) = config
         .resolve_symlink(nix_profile.join("share/applications"))
         .and_then(fs::read_dir)
-        .expect("No applications found");
-
-    let mut table = Table::new();
-    table.add_header(String::from("ID"));
-    table.add_header(String::from("NAME"));
-    table.add_header(String::from("COMMAND"));
-    table.add_header(String::from("COMMENT"));
+    else {
+        return Some(vec![]);
+    };
 
+    let mut apps = vec![];
     for entry in appdir {
         let entry = entry
             .and_then(|x| config.resolve_symlink(x.path()))
             .expect("Error occurred while finding applications");
-        let Some(desktop) = DesktopFile::parse_file(&entry) else {
+        if let Some(desktop) = DesktopFile::parse_file(&entry) {
+            apps.push(desktop);
+        }
+    }
+    apps.sort_by(|a, b| a.id.cmp(&b.id));
+    Some(apps)
+}
+
+/// Application with the given ID in the box's profile
+pub fn find(config: &Config, id: &str) -> Option<DesktopFile> {
+    find_all(config)?.into_iter().find(|app| app.id == id)
+}
+
+/// Directory on the host where exported desktop entries are written
+pub fn host_applications_dir() -> PathBuf {
+    host_data_home().join("applications")
+}
+
+/// Write a desktop entry for `app` to the host which runs it inside the box
+pub fn export(config: &Config, app: &DesktopFile) -> io::Result<PathBuf> {
+    let appdir = host_applications_dir();
+    fs::create_dir_all(&appdir)?;
+
+    let source = fs::read_to_string(&app.path)?;
+    let path = appdir.join(Exported::file_name(&app.id));
+    File::create(&path)?.write_all(rewrite_entry(config, app, &source).as_bytes())?;
+
+    if app.dbus_activatable {
+        export_dbus_service(config, &app.id)?;
+    }
+    Ok(path)
+}
+
+/// Directory on the host where the session bus looks for activatable services
+pub fn host_dbus_services_dir() -> PathBuf {
+    host_data_home().join("dbus-1/services")
+}
+
+/// Export the D-Bus service file of a `DBusActivatable` application, so that
+/// the host's session bus starts it inside the box
+fn export_dbus_service(config: &Config, id: &str) -> io::Result<()> {
+    let source = config
+        .nix_profile
+        .as_ref()
+        .map(|x| {
+            x.join("share/dbus-1/services")
+                .join(format!("{}.service", id))
+        })
+        .and_then(|x| config.resolve_symlink(x).ok());
+    let Some(source) = source else {
+        eprintln!("Warning: '{}' has no D-Bus service file in the box", id);
+        return Ok(());
+    };
+
+    let executable = quote_exec_arg(&config.nixbox_executable().to_string_lossy());
+    let text = rewrite_service(&fs::read_to_string(source)?, &executable, id);
+
+    let dir = host_dbus_services_dir();
+    fs::create_dir_all(&dir)?;
+    File::create(dir.join(format!("{}.service", id)))?.write_all(text.as_bytes())
+}
+
+/// Have the D-Bus service file `text` of the application `id` run its
+/// command in the box, with the settings of the app, as exported entries do
+fn rewrite_service(text: &str, executable: &str, id: &str) -> String {
+    let mut rewritten = format!("{}\n", DBUS_SERVICE_MARKER);
+    for line in text.lines() {
+        match line.trim_start().strip_prefix("Exec=") {
+            Some(exec) => rewritten.push_str(&format!(
+                "Exec={} run --app {} -- {}\n",
+                executable,
+                id,
+                exec.trim()
+            )),
+            None => {
+                rewritten.push_str(line);
+                rewritten.push('\n');
+            }
+        }
+    }
+    rewritten
+}
+
+/// Activate a `DBusActivatable` application through the host's session bus,
+/// optionally asking it to open `uris`
+pub fn activate(app: &DesktopFile, uris: &[String]) -> zbus::Result<()> {
+    let conn = zbus::blocking::Connection::session()?;
+    let path = format!("/{}", app.id.replace('.', "/").replace('-', "_"));
+    let proxy = zbus::blocking::Proxy::new(
+        &conn,
+        app.id.as_str(),
+        path.as_str(),
+        "org.freedesktop.Application",
+    )?;
+
+    let mut platform_data: HashMap<&str, Value> = HashMap::new();
+    if let Ok(token) = env::var("XDG_ACTIVATION_TOKEN") {
+        platform_data.insert("activation-token", Value::from(token));
+    }
+    if let Ok(id) = env::var("DESKTOP_STARTUP_ID") {
+        platform_data.insert("desktop-startup-id", Value::from(id));
+    }
+
+    if uris.is_empty() {
+        proxy.call_method("Activate", &(platform_data,))?;
+    } else {
+        let uris: Vec<String> = uris.iter().map(|x| to_uri(x)).collect();
+        proxy.call_method("Open", &(uris, platform_data))?;
+    }
+    Ok(())
+}
+
+/// Turn a command line argument into a URI, treating anything that doesn't
+/// start with a scheme as a local path
+fn to_uri(arg: &str) -> String {
+    let has_scheme = arg
+        .split_once(':')
+        .map(|(scheme, _)| {
+            !scheme.is_empty()
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        })
+        .unwrap_or(false);
+    if has_scheme {
+        return arg.to_string();
+    }
+
+    let path = env::current_dir()
+        .map(|cwd| cwd.join(arg))
+        .unwrap_or_else(|_| PathBuf::from(arg));
+    let mut uri = String::from("file://");
+    for byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(*byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// Rewrite a desktop entry from the box so that it can be used on the host:
+/// every `Exec=` goes through `nixbox run`, the entry is tagged with the box
+/// and the ID it was exported from, and it is filed under the box's submenu.
+fn rewrite_entry(config: &Config, app: &DesktopFile, source: &str) -> String {
+    let executable = quote_exec_arg(&config.nixbox_executable().to_string_lossy());
+    let suffix = config.settings.get("Export", "NameSuffix");
+    let main_group = format!("[{}]", DESKTOP_ENTRY);
+
+    let mut text = String::new();
+    let mut in_main_group = false;
+    for line in source.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('[') {
+            in_main_group = trimmed.trim_end() == main_group;
+        }
+
+        if trimmed.starts_with("TryExec=") || trimmed.starts_with("Categories=") {
+            // The host cannot see the box's binaries, and the categories
+            // are replaced by the box's own menu category
             continue;
-        };
+        } else if let (true, true, Some(_)) = (
+            in_main_group,
+            app.dbus_activatable,
+            trimmed.strip_prefix("Exec="),
+        ) {
+            // The host would activate the entry by its (renamed) file ID, so
+            // let brief do the activation instead
+            text.push_str(&format!("Exec={} app run {} %U\n", executable, app.id));
+        } else if let Some(exec) = trimmed.strip_prefix("Exec=") {
+            text.push_str(&format!(
+                "Exec={} run --app {} -- {}\n",
+                executable,
+                app.id,
+                exec.trim()
+            ));
+        } else if in_main_group && trimmed.starts_with("DBusActivatable=") {
+            text.push_str("DBusActivatable=false\n");
+        } else if let Some(icon) = trimmed.strip_prefix("Icon=") {
+            text.push_str(&format!("Icon={}\n", resolve_icon(config, icon.trim())));
+        } else if let (true, Some(suffix), true) = (in_main_group, suffix, is_name_key(trimmed)) {
+            text.push_str(&format!("{} {}\n", trimmed.trim_end(), suffix));
+        } else {
+            text.push_str(line);
+            text.push('\n');
+        }
 
-        let id: String = entry.file_stem().unwrap().to_str().unwrap().to_string();
+        if trimmed.trim_end() == main_group {
+            text.push_str(&format!("X-Brief-Box={}\n", BOX_NAME));
+            text.push_str(&format!("X-Brief-Source={}\n", app.id));
+            text.push_str(&format!("Categories={};\n", menu::category()));
+        }
+    }
+    text
+}
 
-        table.add_row(vec![
-            id,
-            desktop.name,
-            desktop.exec,
-            desktop.comment.unwrap_or(String::new()),
-        ])
+/// Whether `line` sets `Name` or one of its localised variants
+fn is_name_key(line: &str) -> bool {
+    let Some((key, _)) = line.split_once('=') else {
+        return false;
+    };
+    let key = key.trim();
+    key == "Name" || (key.starts_with("Name[") && key.ends_with(']'))
+}
+
+/// Icons installed in the box are not in the host's icon theme search path,
+/// so point the host at the file directly when we can find it.
+fn resolve_icon(config: &Config, icon: &str) -> String {
+    if let Some(path) = icon.strip_prefix("/nix/") {
+        return config.nix_home.join(path).display().to_string();
     }
+    let Some(nix_profile) = &config.nix_profile else {
+        return icon.to_string();
+    };
 
-    table.print();
+    let sizes = [
+        "scalable", "512x512", "256x256", "128x128", "64x64", "48x48",
+    ];
+    let candidates = sizes.iter().flat_map(|size| {
+        ["svg", "png"]
+            .iter()
+            .map(move |ext| format!("share/icons/hicolor/{}/apps/{}.{}", size, icon, ext))
+    });
 
-    ExitCode::FAILURE
+    for candidate in candidates.chain([format!("share/pixmaps/{}.png", icon)]) {
+        if let Ok(path) = config.resolve_symlink(nix_profile.join(candidate)) {
+            return path.display().to_string();
+        }
+    }
+    icon.to_string()
 }
 
-struct DesktopFile {
-    name: String,
-    exec: String,
-    comment: Option<String>,
+/// Quote an argument according to the rules of the desktop entry `Exec` key
+fn quote_exec_arg(arg: &str) -> String {
+    const RESERVED: &[char] = &[
+        ' ', '\t', '\n', '"', '\'', '\\', '>', '<', '~', '|', '&', ';', '$', '*', '?', '#', '(',
+        ')', '`',
+    ];
+    if !arg.contains(RESERVED) {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        if matches!(c, '"' | '`' | '$' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
 }
 
-impl DesktopFile {
-    fn parse_file(path: impl AsRef<Path>) -> Option<Self> {
-        let file = File::open(path).ok()?;
-        let reader = BufReader::new(file);
-
-        let mut name = None;
-        let mut exec = None;
-        let mut comm = None;
-        for line in reader.lines() {
-            let line = line.ok()?;
-
-            if let Some(val) = line.strip_prefix("Exec=") {
-                exec = Some(val.trim().to_string());
-            } else if let Some(val) = line.strip_prefix("Name=") {
-                name = Some(val.trim().to_string());
-            } else if let Some(val) = line.strip_prefix("Comment=") {
-                comm = Some(val.trim().to_string());
-            } else if line.starts_with("NoDisplay=true") || line.starts_with("Terminal=true") {
-                return None;
-            } else if let Some(val) = line.strip_prefix("Categories=") {
-                if val
-                    .trim()
-                    .split(';')
-                    .any(|category| category == "ConsoleOnly")
-                {
-                    return None;
+/// Split the value of a desktop entry `Exec` key into an argument vector,
+/// expanding the file and URL field codes with `uris`. Other field codes are
+/// dropped. Returns `None` when the quoting is invalid.
+pub fn parse_exec(exec: &str, uris: &[String]) -> Option<Vec<String>> {
+    // Undo the escapes of the string type first, then split on the quoting
+    // rules of the Exec key
+    let mut unescaped = String::new();
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('s')) => unescaped.push(' '),
+            ('\\', Some('n')) => unescaped.push('\n'),
+            ('\\', Some('t')) => unescaped.push('\t'),
+            ('\\', Some('r')) => unescaped.push('\r'),
+            ('\\', Some('\\')) => unescaped.push('\\'),
+            _ => {
+                unescaped.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+
+    let mut tokens: Vec<(String, bool)> = vec![];
+    let mut token: Option<(String, bool)> = None;
+    let mut chars = unescaped.chars();
+    while let Some(c) = chars.next() {
+        match (c, &mut token) {
+            ('"', Some((_, true))) => {
+                tokens.extend(token.take());
+            }
+            ('\\', Some((arg, true))) => arg.push(chars.next()?),
+            (_, Some((arg, true))) => arg.push(c),
+            ('"', _) => {
+                tokens.extend(token.take());
+                token = Some((String::new(), true));
+            }
+            (' ' | '\t' | '\n', _) => tokens.extend(token.take()),
+            (_, Some((arg, false))) => arg.push(c),
+            (_, None) => token = Some((c.to_string(), false)),
+        }
+    }
+    match token {
+        Some((_, true)) => return None,
+        Some(token) => tokens.push(token),
+        None => (),
+    }
+
+    let mut argv = vec![];
+    for (token, quoted) in tokens {
+        if quoted {
+            argv.push(token);
+            continue;
+        }
+        match token.as_str() {
+            "%F" | "%U" => argv.extend(uris.iter().cloned()),
+            "%f" | "%u" => argv.extend(uris.first().cloned()),
+            _ => {
+                let mut arg = String::new();
+                let mut chars = token.chars();
+                while let Some(c) = chars.next() {
+                    if c != '%' {
+                        arg.push(c);
+                        continue;
+                    }
+                    match chars.next() {
+                        Some('%') => arg.push('%'),
+                        Some('f' | 'u') => arg.push_str(uris.first().map_or("", String::as_str)),
+                        _ => (),
+                    }
+                }
+                if !arg.is_empty() {
+                    argv.push(arg);
                 }
             }
         }
+    }
+    Some(argv)
+}
+
+/// A desktop entry that was exported to the host by brief
+pub struct Exported {
+    pub id: String,
+    pub path: PathBuf,
+}
+
+impl Exported {
+    /// Remove the exported desktop entry, and the D-Bus service file if we
+    /// exported one
+    pub fn remove(&self) -> io::Result<()> {
+        fs::remove_file(&self.path)?;
+
+        let service = host_dbus_services_dir().join(format!("{}.service", self.id));
+        let ours = fs::read_to_string(&service)
+            .map(|text| text.starts_with(DBUS_SERVICE_MARKER))
+            .unwrap_or(false);
+        if ours {
+            fs::remove_file(service)?;
+        }
+        Ok(())
+    }
+
+    pub fn file_name(id: &str) -> String {
+        format!("brief-{}-{}.desktop", BOX_NAME, id)
+    }
+
+    pub fn find_all() -> Vec<Self> {
+        let Ok(dir) = fs::read_dir(host_applications_dir()) else {
+            return vec![];
+        };
+
+        let mut exported: Vec<Self> = dir
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| Self::parse_file(entry.path()))
+            .collect();
+        exported.sort_by(|a, b| a.id.cmp(&b.id));
+        exported
+    }
+
+    fn parse_file(path: PathBuf) -> Option<Self> {
+        if path.extension()? != "desktop" {
+            return None;
+        }
+
+        let keyfile = KeyFile::parse_file(&path).ok()?;
+        if keyfile.get(DESKTOP_ENTRY, "X-Brief-Box")? != BOX_NAME {
+            return None;
+        }
+        let id = keyfile.get(DESKTOP_ENTRY, "X-Brief-Source")?.to_string();
+        Some(Self { id, path })
+    }
+}
+
+pub struct DesktopFile {
+    pub id: String,
+    pub path: PathBuf,
+    pub name: String,
+    pub exec: String,
+    pub comment: Option<String>,
+    pub mime_types: Vec<String>,
+    pub dbus_activatable: bool,
+}
+
+impl DesktopFile {
+    fn parse_file(path: impl AsRef<Path>) -> Option<Self> {
+        let path = path.as_ref();
+        let keyfile = KeyFile::parse_file(path).ok()?;
+        let entry = keyfile.group(DESKTOP_ENTRY)?;
+
+        if entry.get_bool("NoDisplay") == Some(true) || entry.get_bool("Terminal") == Some(true) {
+            return None;
+        }
+        if entry
+            .get_list("Categories")
+            .iter()
+            .any(|category| category == "ConsoleOnly")
+        {
+            return None;
+        }
 
         Some(Self {
-            name: name?,
-            exec: exec?,
-            comment: comm,
+            id: path.file_stem()?.to_str()?.to_string(),
+            path: path.to_owned(),
+            name: entry.get("Name")?.to_string(),
+            exec: entry.get("Exec")?.to_string(),
+            comment: entry.get("Comment").map(String::from),
+            mime_types: entry.get_list("MimeType"),
+            dbus_activatable: entry.get_bool("DBusActivatable") == Some(true),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_exec_with_field_codes() {
+        let uris = vec![String::from("a.txt"), String::from("b.txt")];
+
+        assert_eq!(
+            parse_exec("gedit --new-window %U", &uris).unwrap(),
+            ["gedit", "--new-window", "a.txt", "b.txt"]
+        );
+        assert_eq!(parse_exec("gedit %f", &uris).unwrap(), ["gedit", "a.txt"]);
+        assert_eq!(parse_exec("gedit %F %i %c", &[]).unwrap(), ["gedit"]);
+        assert_eq!(parse_exec("printf 100%%", &[]).unwrap(), ["printf", "100%"]);
+    }
+
+    #[test]
+    fn it_runs_dbus_services_as_the_app() {
+        let text = "[D-BUS Service]\nName=org.example.App\nExec=/bin/app --gapplication-service\n";
+        let rewritten = rewrite_service(text, "/bin/nixbox", "org.example.App");
+
+        assert!(rewritten.starts_with(DBUS_SERVICE_MARKER));
+        assert!(rewritten.contains("\nName=org.example.App\n"));
+        assert!(rewritten.contains(
+            "\nExec=/bin/nixbox run --app org.example.App -- /bin/app --gapplication-service\n"
+        ));
+    }
+
+    #[test]
+    fn it_parses_exec_quoting() {
+        assert_eq!(
+            parse_exec(r#""/opt/my app/bin" --title "say \\"hi\\"" "%u""#, &[]).unwrap(),
+            ["/opt/my app/bin", "--title", "say \"hi\"", "%u"]
+        );
+        assert_eq!(
+            parse_exec(r#"sh -c "echo \\$HOME""#, &[]).unwrap(),
+            ["sh", "-c", "echo $HOME"]
+        );
+        assert!(parse_exec(r#"sh -c "unterminated"#, &[]).is_none());
+    }
+
+    #[test]
+    fn it_roundtrips_quoted_arguments() {
+        let arg = "/home/me/my \"box\"/nixbox";
+        let exec = format!("{} run", quote_exec_arg(arg));
+        assert_eq!(parse_exec(&exec, &[]).unwrap(), [arg, "run"]);
+    }
+}
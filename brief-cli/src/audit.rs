@@ -0,0 +1,410 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, ExitCode};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::{Config, BOX_NAME};
+use crate::jobs::format_age;
+use crate::output::{Field, Format, Listing, Output};
+
+/// Size above which the audit log is moved aside to `.1` and started anew
+const LOG_SIZE: u64 = 1024 * 1024;
+
+/// The box's audit log, with a line when a command starts and another when
+/// it exits
+pub fn log_path(config: &Config) -> PathBuf {
+    config
+        .xdg_state_home()
+        .join("brief/history")
+        .join(format!("{}.tsv", BOX_NAME))
+}
+
+/// A command run in the box, as a line of the audit log: time, id,
+/// session, exit code, duration, working directory, system, profile and
+/// then the arguments, separated by tabs
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    /// Seconds since the epoch when the command started
+    pub time: u64,
+    /// The same in both lines of a command, to tell it from others started
+    /// in the same second: the pid and start time of the process writing
+    /// them
+    pub id: String,
+    /// How the command was started: `run`, `enter`, `app`, `shim`, `job`
+    /// or `dbus`
+    pub session: String,
+    /// `None` for the line written when the command starts
+    pub exit_code: Option<i32>,
+    /// Seconds the command ran for
+    pub duration: f64,
+    pub cwd: String,
+    /// Store path of the box's system
+    pub system: Option<String>,
+    /// Store path of the user's profile, unless it was left out
+    pub profile: Option<String>,
+    pub argv: Vec<String>,
+}
+
+impl Record {
+    fn to_line(&self) -> String {
+        let optional = |x: &Option<String>| x.as_deref().map(escape).unwrap_or_default();
+        let mut columns = vec![
+            self.time.to_string(),
+            escape(&self.id),
+            escape(&self.session),
+            self.exit_code.map(|x| x.to_string()).unwrap_or_default(),
+            format!("{:.3}", self.duration),
+            escape(&self.cwd),
+            optional(&self.system),
+            optional(&self.profile),
+        ];
+        columns.extend(self.argv.iter().map(|x| escape(x)));
+        columns.join("\t")
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let mut columns = line.split('\t');
+        let mut optional = || {
+            let column = columns.next()?;
+            Some((!column.is_empty()).then(|| unescape(column)))
+        };
+        let time = optional()??.parse().ok()?;
+        let id = optional()??;
+        let session = optional()??;
+        let exit_code = optional()?.and_then(|x| x.parse().ok());
+        let duration = optional()?.and_then(|x| x.parse().ok()).unwrap_or_default();
+        let cwd = optional()?.unwrap_or_default();
+        let system = optional()?;
+        let profile = optional()?;
+        Some(Self {
+            time,
+            id,
+            session,
+            exit_code,
+            duration,
+            cwd,
+            system,
+            profile,
+            argv: columns.map(unescape).collect(),
+        })
+    }
+}
+
+/// An audit record in the making. The log is opened when the command
+/// starts, so that it can still be written once we're in the box, where it
+/// might be out of reach. The record is written once before the command
+/// runs, so that commands which never finish are recorded too, and again
+/// with the exit code.
+pub struct Entry {
+    file: Option<File>,
+    started: Instant,
+    record: Record,
+}
+
+impl Entry {
+    pub fn start(config: &Config, session: &str, argv: &[String]) -> Self {
+        let path = log_path(config);
+        let file = path
+            .parent()
+            .map(fs::create_dir_all)
+            .unwrap_or(Ok(()))
+            .and_then(|_| rotate(&path))
+            .and_then(|_| File::options().create(true).append(true).open(&path));
+        let file = match file {
+            Ok(file) => Some(file),
+            Err(err) => {
+                eprintln!("Could not open '{}': {}", path.display(), err);
+                None
+            }
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let record = Record {
+            time: now.as_secs(),
+            id: format!("{}-{}", process::id(), now.as_nanos()),
+            session: session.to_string(),
+            exit_code: None,
+            duration: 0.0,
+            cwd: String::new(),
+            system: store_path(config, config.nixbox_root()),
+            profile: config
+                .nix_profile
+                .as_ref()
+                .and_then(|x| store_path(config, x)),
+            argv: argv.to_vec(),
+        };
+        Self {
+            file,
+            started: Instant::now(),
+            record,
+        }
+    }
+
+    /// Append the record of the command, which is about to run in `cwd`
+    pub fn begin(&mut self, cwd: &Path) {
+        self.record.cwd = cwd.to_string_lossy().into_owned();
+        self.write(&self.record);
+    }
+
+    /// Append the record of the command again, now that it exited with
+    /// `code`
+    pub fn finish(self, code: i32) {
+        let record = Record {
+            exit_code: Some(code),
+            duration: self.started.elapsed().as_secs_f64(),
+            ..self.record.clone()
+        };
+        self.write(&record);
+    }
+
+    fn write(&self, record: &Record) {
+        let Some(mut file) = self.file.as_ref() else {
+            return;
+        };
+        // One write, so that concurrent sessions don't interleave
+        if let Err(err) = file.write_all(format!("{}\n", record.to_line()).as_bytes()) {
+            eprintln!("Could not write audit log: {}", err);
+        }
+    }
+}
+
+/// Move the log at `path` aside once it grew past `LOG_SIZE`, replacing the
+/// one moved aside before
+fn rotate(path: &Path) -> io::Result<()> {
+    if fs::metadata(path).map(|x| x.len()).unwrap_or_default() > LOG_SIZE {
+        fs::rename(path, rotated_path(path))?;
+    }
+    Ok(())
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    PathBuf::from(rotated)
+}
+
+/// The records of the log at `path` and the one moved aside before it,
+/// in the order the commands started. The line written when a command
+/// started is replaced by the one written when it finished.
+fn read_log(path: &Path) -> Vec<Record> {
+    let mut records: Vec<Record> = vec![];
+    for path in [rotated_path(path), path.to_path_buf()] {
+        let Ok(file) = File::open(&path) else {
+            continue;
+        };
+        for record in BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| Record::parse(&line))
+        {
+            match record
+                .exit_code
+                .and(records.iter().rposition(|x| x.id == record.id))
+            {
+                Some(i) => records[i] = record,
+                None => records.push(record),
+            }
+        }
+    }
+    records
+}
+
+/// The store path that the symlink `link` ends up at, as seen in the box,
+/// or its target if it can't be followed all the way
+fn store_path(config: &Config, link: &Path) -> Option<String> {
+    let resolved = match config.resolve_symlink(link) {
+        Ok(resolved) => resolved,
+        Err(_) => fs::read_link(link).ok()?,
+    };
+    let path = match resolved.strip_prefix(&config.nix_home) {
+        Ok(rest) => Path::new("/nix").join(rest),
+        Err(_) => resolved,
+    };
+    Some(path.to_string_lossy().into_owned())
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+pub fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Parse an age like `90s`, `30m`, `2h` or `7d` into seconds. A plain
+/// number is seconds.
+pub fn parse_age(text: &str) -> Result<u64, String> {
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => text.split_at(i),
+        None => (text, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid age '{}'", text))?;
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 7 * 86400,
+        _ => return Err(format!("unknown unit in '{}': use s, m, h, d or w", text)),
+    };
+    Ok(number * unit)
+}
+
+/// What to show of the audit log
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub command: Option<String>,
+    pub session: Option<String>,
+    pub dir: Option<PathBuf>,
+    pub failed: bool,
+    /// Only commands started in the last this many seconds
+    pub since: Option<u64>,
+    /// Only the latest this many commands
+    pub limit: Option<usize>,
+}
+
+impl Filter {
+    fn matches(&self, record: &Record, now: u64) -> bool {
+        self.command
+            .as_ref()
+            .is_none_or(|x| record.argv.join(" ").contains(x.as_str()))
+            && self.session.as_ref().is_none_or(|x| *x == record.session)
+            && self
+                .dir
+                .as_ref()
+                .is_none_or(|x| Path::new(&record.cwd).starts_with(x))
+            && (!self.failed || record.exit_code.is_some_and(|x| x != 0))
+            && self
+                .since
+                .is_none_or(|x| now.saturating_sub(record.time) <= x)
+    }
+}
+
+/// Show the commands run in the box that pass `filter`, oldest first
+pub fn history(config: &Config, output: &Output, filter: &Filter) -> ExitCode {
+    let records = read_log(&log_path(config));
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default();
+    let mut records: Vec<Record> = records
+        .into_iter()
+        .filter(|x| filter.matches(x, now))
+        .collect();
+    if let Some(limit) = filter.limit {
+        records.drain(..records.len().saturating_sub(limit));
+    }
+    if records.is_empty() && output.format == Format::Table {
+        println!("No commands recorded");
+        return ExitCode::SUCCESS;
+    }
+
+    let mut listing = Listing::new(&[
+        ("time", ""),
+        ("age", "STARTED"),
+        ("session", "SESSION"),
+        ("exit_code", "EXIT"),
+        ("duration", "DURATION"),
+        ("cwd", "DIRECTORY"),
+        ("system", ""),
+        ("profile", ""),
+        ("command", "COMMAND"),
+    ]);
+    listing.truncate(&["cwd", "command"]);
+    for record in records {
+        listing.add_row(vec![
+            (record.time as i64).into(),
+            format!("{} ago", format_age(now.saturating_sub(record.time))).into(),
+            record.session.into(),
+            record.exit_code.into(),
+            Field::Float(record.duration),
+            record.cwd.into(),
+            record.system.into(),
+            record.profile.into(),
+            record.argv.join(" ").into(),
+        ]);
+    }
+    listing.print(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testdir::testdir;
+
+    #[test]
+    fn it_reads_back_records() {
+        let record = Record {
+            time: 1700000000,
+            id: String::from("42-1700000000000000000"),
+            session: String::from("run"),
+            exit_code: Some(1),
+            duration: 0.25,
+            cwd: String::from("/home/me"),
+            system: Some(String::from("/nix/store/abc-nixos-system")),
+            profile: None,
+            argv: vec![String::from("echo"), String::from("a\tb\\n")],
+        };
+
+        assert_eq!(Record::parse(&record.to_line()), Some(record));
+    }
+
+    #[test]
+    fn it_replaces_the_start_of_finished_commands() {
+        let path = testdir!().join("history.tsv");
+        // Alike but for the id, as if run twice in the same second
+        let started = |id: &str| Record {
+            time: 1700000000,
+            id: id.to_string(),
+            session: String::from("run"),
+            exit_code: None,
+            duration: 0.0,
+            cwd: String::from("/"),
+            system: None,
+            profile: None,
+            argv: vec![String::from("true")],
+        };
+        let finished = Record {
+            exit_code: Some(0),
+            duration: 1.5,
+            ..started("2-1")
+        };
+        let lines = [started("1-1"), started("2-1"), finished.clone()].map(|x| x.to_line() + "\n");
+        fs::write(&path, lines.concat()).unwrap();
+
+        assert_eq!(read_log(&path), vec![started("1-1"), finished]);
+    }
+
+    #[test]
+    fn it_parses_ages() {
+        assert_eq!(parse_age("90"), Ok(90));
+        assert_eq!(parse_age("30m"), Ok(1800));
+        assert_eq!(parse_age("7d"), Ok(604800));
+        assert!(parse_age("2y").is_err());
+        assert!(parse_age("m").is_err());
+    }
+}
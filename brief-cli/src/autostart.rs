@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Stdio};
+use std::thread;
+
+use crate::app::parse_exec;
+use crate::command::command;
+use crate::config::Config;
+use crate::landlock;
+use crate::limits::Limits;
+use crate::util::KeyFile;
+
+/// Launch the XDG autostart entries of the box. Entries in the box's
+/// `XDG_CONFIG_HOME` override those of the system with the same name.
+pub fn start(config: &Config) {
+    let mut dirs = vec![];
+    if let Some(current_system) = &config.current_system {
+        dirs.push(current_system.join("etc/xdg/autostart"));
+    }
+    dirs.push(config.xdg_config_home().join("autostart"));
+
+    let mut entries: BTreeMap<String, PathBuf> = BTreeMap::new();
+    for dir in dirs {
+        let Ok(dir) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in dir.filter_map(|x| x.ok()) {
+            let path = entry.path();
+            if path.extension().map(|x| x == "desktop").unwrap_or(false) {
+                let id = path.file_stem().unwrap().to_string_lossy().to_string();
+                entries.insert(id, path);
+            }
+        }
+    }
+
+    let logdir = config.xdg_state_home().join("brief/autostart");
+    if let Err(err) = fs::create_dir_all(&logdir) {
+        eprintln!("Could not create '{}': {}", logdir.display(), err);
+        return;
+    }
+
+    for (id, path) in entries {
+        match launch(config, &id, &path, &logdir.join(format!("{}.log", id))) {
+            Ok(Some(mut child)) => {
+                println!("Autostarted {} (PID: {})", id, child.id());
+                // Reap the child so that it doesn't linger as a zombie
+                thread::spawn(move || child.wait());
+            }
+            Ok(None) => (),
+            Err(err) => eprintln!("Could not autostart {}: {}", id, err),
+        }
+    }
+}
+
+fn launch(config: &Config, id: &str, path: &Path, logfile: &Path) -> Result<Option<Child>, String> {
+    let keyfile = KeyFile::parse_file(path).map_err(|err| err.to_string())?;
+    let Some(entry) = keyfile.group("Desktop Entry") else {
+        return Err(String::from("not a desktop entry"));
+    };
+
+    if entry.get_bool("Hidden") == Some(true)
+        || entry.get_bool("X-GNOME-Autostart-enabled") == Some(false)
+    {
+        return Ok(None);
+    }
+
+    let desktops: Vec<String> = env::var("XDG_CURRENT_DESKTOP")
+        .unwrap_or_default()
+        .split(':')
+        .map(String::from)
+        .collect();
+    let only_show_in = entry.get_list("OnlyShowIn");
+    if !only_show_in.is_empty() && !only_show_in.iter().any(|x| desktops.contains(x)) {
+        return Ok(None);
+    }
+    if entry
+        .get_list("NotShowIn")
+        .iter()
+        .any(|x| desktops.contains(x))
+    {
+        return Ok(None);
+    }
+
+    let exec = entry.get("Exec").ok_or("no Exec key")?;
+    let argv = parse_exec(exec, &[]).ok_or("invalid Exec key")?;
+    let Some((program, args)) = argv.split_first() else {
+        return Err(String::from("empty Exec key"));
+    };
+
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(logfile)
+        .map_err(|err| format!("{}: {}", logfile.display(), err))?;
+    let envs: Vec<(String, String)> = vec![];
+    let mut command = command(config, program, args, envs);
+    Limits::from_settings(&config.settings)
+        .unwrap_or_default()
+        .apply_to(&mut command);
+    landlock::apply_to(&landlock::app_rules(&config.settings, id)?, &mut command)?;
+    command
+        .stdin(Stdio::null())
+        .stdout(log.try_clone().map_err(|err| err.to_string())?)
+        .stderr(log)
+        .spawn()
+        .map(Some)
+        .map_err(|err| format!("{}: {}", program, err))
+}
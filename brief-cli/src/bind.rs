@@ -49,7 +49,7 @@ fn bind_dir(path: &Path, target: &Path) {
             .unwrap_or_else(|err| panic!("Could not list dir '{}': {}", target.display(), err))
         {
             let entry = entry.unwrap();
-            bind(&entry.path(), target);
+            bind(entry.path(), target);
         }
     }
 }
@@ -1,5 +1,7 @@
 mod install;
+mod pty;
 mod run;
 
 pub use install::install;
-pub use run::run;
+pub use pty::run_command_pty;
+pub use run::{clean_command, command, exit_code, resolve_workdir, run, run_command};
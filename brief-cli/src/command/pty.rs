@@ -0,0 +1,153 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+
+use nix::pty::{openpty, Winsize};
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg};
+use nix::unistd::{isatty, setsid};
+
+use super::run::{exit_code, forward_signals};
+
+/// Master side of the pty, for the SIGWINCH handler
+static MASTER: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn resize(_: libc::c_int) {
+    if let Some(size) = window_size() {
+        unsafe { libc::ioctl(MASTER.load(Ordering::SeqCst), libc::TIOCSWINSZ, &size) };
+    }
+}
+
+/// The size of the terminal on whichever of our stdio is one. Safe to call
+/// from a signal handler.
+fn window_size() -> Option<Winsize> {
+    let mut size = Winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO]
+        .iter()
+        .any(|fd| unsafe { libc::ioctl(*fd, libc::TIOCGWINSZ, &mut size) } == 0)
+        .then_some(size)
+}
+
+/// Like `run_command`, but with the program on a new pseudo-terminal. Our
+/// stdio is relayed to it, with the terminal in raw mode when stdin is one, so
+/// that full-screen programs work even when we aren't started from a terminal.
+pub fn run_command_pty(command: &mut Command) -> i32 {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let size = window_size().unwrap_or(Winsize {
+        ws_row: 24,
+        ws_col: 80,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    });
+    let pty = openpty(&size, None).unwrap_or_else(|err| panic!("Could not open a pty: {}", err));
+
+    let slave = |pty: &nix::pty::OpenptyResult| {
+        pty.slave
+            .try_clone()
+            .unwrap_or_else(|err| panic!("Could not duplicate pty: {}", err))
+    };
+    command
+        .stdin(Stdio::from(slave(&pty)))
+        .stdout(Stdio::from(slave(&pty)))
+        .stderr(Stdio::from(pty.slave));
+    unsafe {
+        command.pre_exec(|| {
+            setsid()?;
+            if libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    let child = command.spawn();
+    // Close our copies of the slave, so that reading the master fails once
+    // the program and its children are done with it
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            eprintln!("failed to execute {}: {}", program, err);
+            return 1;
+        }
+    };
+
+    // The program is in its own session, so nothing from our terminal
+    // reaches it but what we forward
+    forward_signals(child.id(), true);
+    MASTER.store(pty.master.as_raw_fd(), Ordering::SeqCst);
+    let action = SigAction::new(
+        SigHandler::Handler(resize),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    unsafe { sigaction(Signal::SIGWINCH, &action) }
+        .unwrap_or_else(|err| panic!("Could not handle {}: {}", Signal::SIGWINCH, err));
+
+    let stdin = io::stdin();
+    let saved = match isatty(stdin.as_raw_fd()) {
+        Ok(true) => tcgetattr(&stdin).ok(),
+        _ => None,
+    };
+    if let Some(saved) = &saved {
+        let mut raw = saved.clone();
+        cfmakeraw(&mut raw);
+        tcsetattr(&stdin, SetArg::TCSANOW, &raw)
+            .unwrap_or_else(|err| eprintln!("Could not set raw mode: {}", err));
+    }
+
+    let mut master = File::from(pty.master);
+    let mut input = master
+        .try_clone()
+        .unwrap_or_else(|err| panic!("Could not duplicate pty: {}", err));
+    thread::spawn(move || {
+        let mut stdin = io::stdin();
+        let _ = io::copy(&mut stdin, &mut input);
+        // Let the program see the end of input, like ^D on a terminal
+        let _ = input.write_all(&[4]);
+    });
+
+    let mut stdout = io::stdout();
+    let mut buf = [0u8; 4096];
+    loop {
+        match master.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => {
+                if stdout
+                    .write_all(&buf[..len])
+                    .and_then(|_| stdout.flush())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            // EIO once the slave side is closed
+            Err(_) => break,
+        }
+    }
+
+    let status = child.wait();
+    if let Some(saved) = &saved {
+        let _ = tcsetattr(&stdin, SetArg::TCSANOW, saved);
+    }
+    match status {
+        Ok(status) => exit_code(status),
+        Err(err) => {
+            eprintln!("failed to wait for {}: {}", program, err);
+            1
+        }
+    }
+}
@@ -1,7 +1,11 @@
 use std::env;
 use std::ffi::OsStr;
 use std::os::unix::process::ExitStatusExt;
-use std::process::{Command, ExitCode};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, ExitStatus};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
 
 use crate::config::Config;
 
@@ -35,6 +39,79 @@ const FORWARD_VARS: &[&str] = &[
 ];
 
 pub fn run<SP, IA, SA, IE, K, V>(config: &Config, program: SP, args: IA, envs: IE) -> ExitCode
+where
+    SP: AsRef<OsStr>,
+    IA: IntoIterator<Item = SA>,
+    SA: AsRef<OsStr>,
+    IE: IntoIterator<Item = (K, V)>,
+    K: AsRef<OsStr>,
+    V: AsRef<OsStr>,
+{
+    ExitCode::from(run_command(&mut command(config, program, args, envs)) as u8)
+}
+
+/// Run a prepared command, forwarding signals to it, and return its exit code
+/// as in `exit_code`
+pub fn run_command(command: &mut Command) -> i32 {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            eprintln!("failed to execute {}: {}", program, err);
+            return 1;
+        }
+    };
+
+    forward_signals(child.id(), false);
+    match child.wait() {
+        Ok(status) => exit_code(status),
+        Err(err) => {
+            eprintln!("failed to wait for {}: {}", program, err);
+            1
+        }
+    }
+}
+
+/// Build a command with the box's environment: the forwarded variables from
+/// the host, then the box's own, then `envs`.
+pub fn command<SP, IA, SA, IE, K, V>(config: &Config, program: SP, args: IA, envs: IE) -> Command
+where
+    SP: AsRef<OsStr>,
+    IA: IntoIterator<Item = SA>,
+    SA: AsRef<OsStr>,
+    IE: IntoIterator<Item = (K, V)>,
+    K: AsRef<OsStr>,
+    V: AsRef<OsStr>,
+{
+    build_command(config, program, args, envs, true)
+}
+
+/// Like `command`, but without any variables forwarded from the host, also
+/// where the box's environment picked them up from the service
+pub fn clean_command<SP, IA, SA, IE, K, V>(
+    config: &Config,
+    program: SP,
+    args: IA,
+    envs: IE,
+) -> Command
+where
+    SP: AsRef<OsStr>,
+    IA: IntoIterator<Item = SA>,
+    SA: AsRef<OsStr>,
+    IE: IntoIterator<Item = (K, V)>,
+    K: AsRef<OsStr>,
+    V: AsRef<OsStr>,
+{
+    build_command(config, program, args, envs, false)
+}
+
+fn build_command<SP, IA, SA, IE, K, V>(
+    config: &Config,
+    program: SP,
+    args: IA,
+    envs: IE,
+    forward: bool,
+) -> Command
 where
     SP: AsRef<OsStr>,
     IA: IntoIterator<Item = SA>,
@@ -46,23 +123,107 @@ where
     let mut command = Command::new(&program);
     command.args(args).env_clear();
 
-    for key in FORWARD_VARS {
-        if let Some(val) = env::var_os(key) {
-            command.env(key, val);
+    if forward {
+        for key in FORWARD_VARS {
+            if let Some(val) = env::var_os(key) {
+                command.env(key, val);
+            }
         }
     }
 
+    let box_env = config
+        .env
+        .iter()
+        .filter(|(key, _)| forward || !FORWARD_VARS.iter().any(|x| OsStr::new(x) == *key));
+    command.envs(box_env).envs(envs);
     command
-        .envs(&config.env)
-        .envs(envs)
-        .status()
-        .map(|x| ExitCode::from(x.into_raw() as u8))
-        .unwrap_or_else(|err| {
-            eprintln!(
-                "failed to execute {}: {}",
-                program.as_ref().to_string_lossy(),
-                err
-            );
-            ExitCode::FAILURE
-        })
+}
+
+/// Process that signals are forwarded to
+static CHILD: AtomicI32 = AtomicI32::new(0);
+
+/// Whether to forward signals that came from the terminal as well
+static FORWARD_ALL: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn forward_signal(
+    signal: libc::c_int,
+    info: *mut libc::siginfo_t,
+    _: *mut libc::c_void,
+) {
+    // SI_USER, SI_QUEUE and SI_TKILL are all <= 0, while signals generated by
+    // the kernel, eg. ^C on the terminal, are positive
+    let from_process = unsafe { (*info).si_code } <= 0;
+    let pid = CHILD.load(Ordering::SeqCst);
+    if pid > 0 && (from_process || FORWARD_ALL.load(Ordering::SeqCst)) {
+        unsafe { libc::kill(pid, signal) };
+    }
+}
+
+/// Pass SIGINT, SIGTERM and SIGHUP on to `pid` instead of dying from them.
+/// A child in our process group already gets the ones sent by the terminal,
+/// so unless `all` is set only signals sent by other processes are forwarded.
+pub(super) fn forward_signals(pid: u32, all: bool) {
+    CHILD.store(pid as i32, Ordering::SeqCst);
+    FORWARD_ALL.store(all, Ordering::SeqCst);
+
+    let action = SigAction::new(
+        SigHandler::SigAction(forward_signal),
+        SaFlags::SA_SIGINFO | SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for signal in [Signal::SIGINT, Signal::SIGTERM, Signal::SIGHUP] {
+        unsafe { sigaction(signal, &action) }
+            .unwrap_or_else(|err| panic!("Could not handle {}: {}", signal, err));
+    }
+}
+
+/// Exit code of a process the way a shell reports it: the code it exited
+/// with, or 128 plus the number of the signal that killed it.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 1,
+    }
+}
+
+/// `workdir` of `run --workdir`, relative to the current directory. It has
+/// to be resolved before `enterns`, which changes directory.
+pub fn resolve_workdir(workdir: &Path) -> PathBuf {
+    match env::current_dir() {
+        Ok(cwd) => cwd.join(workdir),
+        Err(_) => workdir.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testdir::testdir;
+
+    #[test]
+    fn it_resolves_workdirs_here() {
+        let cwd = env::current_dir().unwrap();
+        assert_eq!(resolve_workdir(Path::new("src")), cwd.join("src"));
+        assert_eq!(resolve_workdir(Path::new("/tmp")), Path::new("/tmp"));
+    }
+
+    #[test]
+    fn it_returns_the_exit_code() {
+        assert_eq!(exit_code(ExitStatus::from_raw(3 << 8)), 3);
+        assert_eq!(exit_code(ExitStatus::from_raw(libc::SIGKILL)), 128 + 9);
+    }
+
+    #[test]
+    fn it_leaves_out_forwarded_vars() {
+        let mut config = Config::for_tests(testdir!()).unwrap();
+        config.env.insert("DISPLAY".into(), ":0".into());
+        config.env.insert("NIX_PATH".into(), "nixpkgs".into());
+        let envs: Vec<(&str, &str)> = vec![];
+
+        let command = clean_command(&config, "true", [""; 0], envs);
+        let vars: Vec<&OsStr> = command.get_envs().map(|(key, _)| key).collect();
+        assert!(!vars.contains(&OsStr::new("DISPLAY")));
+        assert!(vars.contains(&OsStr::new("NIX_PATH")));
+    }
 }
@@ -2,10 +2,14 @@ use std::collections::HashMap;
 use std::env;
 use std::ffi::{OsStr, OsString};
 use std::io::Result;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::path::{Path, PathBuf};
 
 use crate::init::Service;
-use crate::util::{mkdtemp, resolve_symlink};
+use crate::util::{mkdtemp, resolve_symlink, KeyFile};
+
+/// Name of the box. Used as its hostname and to tag files exported to the host.
+pub const BOX_NAME: &str = "nixbox";
 
 fn data_dir() -> Option<PathBuf> {
     if let Some(val) = env::var_os("XDG_DATA_HOME") {
@@ -25,6 +29,24 @@ fn current_system_dir() -> Option<PathBuf> {
     }
 }
 
+/// `XDG_DATA_HOME` of the host, as opposed to the one set up inside the box.
+pub fn host_data_home() -> PathBuf {
+    match env::var_os("XDG_DATA_HOME").filter(|val| !val.is_empty()) {
+        Some(val) => PathBuf::from(val),
+        None => PathBuf::from(env::var_os("HOME").expect("Environment variable HOME not set"))
+            .join(".local/share"),
+    }
+}
+
+/// `XDG_CONFIG_HOME` of the host, as opposed to the one set up inside the box.
+pub fn host_config_home() -> PathBuf {
+    match env::var_os("XDG_CONFIG_HOME").filter(|val| !val.is_empty()) {
+        Some(val) => PathBuf::from(val),
+        None => PathBuf::from(env::var_os("HOME").expect("Environment variable HOME not set"))
+            .join(".config"),
+    }
+}
+
 fn nix_profile_dir() -> Option<PathBuf> {
     let val = env::var_os("HOME")?;
     let path = PathBuf::from(val).join(".nix-profile");
@@ -34,6 +56,7 @@ fn nix_profile_dir() -> Option<PathBuf> {
     }
 }
 
+#[derive(Clone)]
 pub struct Config {
     pub chroot_dir: PathBuf,
     pub nix_profile: Option<PathBuf>,
@@ -41,6 +64,9 @@ pub struct Config {
 
     pub env: HashMap<OsString, OsString>,
     pub nix_home: PathBuf,
+
+    /// Box settings from `brief.conf` in the data directory
+    pub settings: KeyFile,
 }
 
 impl Config {
@@ -50,6 +76,17 @@ impl Config {
         let chroot_dir = mkdtemp(concat!(env!("CARGO_CRATE_NAME"), "-chroot.XXXXXX"))
             .unwrap_or_else(|err| panic!("failed to create temporary directory: {}", err));
 
+        Some(Self::build(data_dir, chroot_dir, use_nix_profile))
+    }
+
+    /// Like `new(false)`, but chrooting to `chroot_dir` rather than a new
+    /// temporary directory that tests would leave behind
+    #[cfg(test)]
+    pub fn for_tests(chroot_dir: PathBuf) -> Option<Self> {
+        Some(Self::build(data_dir()?, chroot_dir, false))
+    }
+
+    fn build(data_dir: PathBuf, chroot_dir: PathBuf, use_nix_profile: bool) -> Self {
         let (nix_profile, current_system) = if use_nix_profile {
             (nix_profile_dir(), current_system_dir())
         } else {
@@ -87,13 +124,14 @@ impl Config {
             ),
         ]);
 
-        Some(Self {
+        Self {
             chroot_dir,
             nix_profile,
             current_system,
             env,
             nix_home: data_dir.join("nix"),
-        })
+            settings: KeyFile::parse_file(data_dir.join("brief.conf")).unwrap_or_default(),
+        }
     }
 
     pub fn xdg_data_home(&self) -> &Path {
@@ -136,18 +174,57 @@ impl Config {
         )
     }
 
+    /// Path to the nixbox executable that host-side wrappers should call.
+    /// Prefers the copy installed into the box over the running executable.
+    pub fn nixbox_executable(&self) -> PathBuf {
+        let installed = self.nixbox_bindir().join("nixbox");
+        if installed.is_file() {
+            installed
+        } else {
+            PathBuf::from(
+                self.env
+                    .get(OsStr::new("NIXBOX_EXECUTABLE"))
+                    .expect("Logic error: env HashMap does not contain NIXBOX_EXECUTABLE"),
+            )
+        }
+    }
+
     pub fn resolve_symlink(&self, path: impl AsRef<Path>) -> Result<PathBuf> {
         let mnt = ("/nix", &self.nix_home);
         resolve_symlink(&mnt, path)
     }
 }
 
-impl From<&Service> for Config {
-    fn from(service: &Service) -> Self {
-        let mut config = Self::new(true).unwrap();
+impl Config {
+    /// Configuration of a running box, with the environment of its service.
+    /// Without `use_nix_profile`, the user's profile is also left out of
+    /// search paths like `PATH` that the service set up.
+    pub fn from_service(service: &Service, use_nix_profile: bool) -> Self {
+        let mut config = Self::new(use_nix_profile).unwrap();
+        let profile = env::var_os("HOME").map(|home| PathBuf::from(home).join(".nix-profile"));
         for (key, val) in service.env.iter() {
-            config.env.insert(key.clone(), val.clone());
+            let val = match &profile {
+                Some(profile) if !use_nix_profile => without_profile(val, profile),
+                _ => val.clone(),
+            };
+            config.env.insert(key.clone(), val);
         }
         config
     }
 }
+
+/// Drop the entries of a `:`-separated search path that are inside `profile`
+fn without_profile(val: &OsStr, profile: &Path) -> OsString {
+    let entries: Vec<&[u8]> = val
+        .as_bytes()
+        .split(|c| *c == b':')
+        .filter(|entry| !Path::new(OsStr::from_bytes(entry)).starts_with(profile))
+        .collect();
+    OsString::from_vec(entries.join(&b':'))
+}
+
+impl From<&Service> for Config {
+    fn from(service: &Service) -> Self {
+        Self::from_service(service, true)
+    }
+}
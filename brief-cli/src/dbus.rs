@@ -0,0 +1,697 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+use zbus::blocking::connection::{Builder, Connection};
+use zbus::message::Header;
+use zbus::object_server::SignalContext;
+use zbus::zvariant::OwnedValue;
+use zbus::{fdo, interface};
+
+use crate::app::{self, parse_exec};
+use crate::audit;
+use crate::command::{clean_command, command, exit_code};
+use crate::config::{Config, BOX_NAME};
+use crate::events::{self, Event, Journal, Reporter};
+use crate::host_exec;
+use crate::init::Service;
+use crate::jobs::{self, Job};
+use crate::landlock;
+use crate::limits::{self, Limits};
+use crate::output::Field;
+use crate::procs;
+use crate::seccomp;
+use crate::status::box_processes;
+use crate::units::{Supervisor, UnitInfo};
+
+pub const NAME: &str = "pink.wah.Brief1";
+pub const PATH: &str = "/pink/wah/Brief1";
+
+/// (pid, cmdline) of processes in the box
+type Processes = Vec<(u32, String)>;
+
+/// Own `pink.wah.Brief1` on the session bus. The name is released when the
+/// returned connection is dropped.
+pub fn serve(config: Config, units: Supervisor, events: Journal) -> zbus::Result<Connection> {
+    build(Builder::session()?, config, units, events)
+}
+
+pub(crate) fn build(
+    builder: Builder,
+    config: Config,
+    units: Supervisor,
+    events: Journal,
+) -> zbus::Result<Connection> {
+    let conn = builder
+        .name(NAME)?
+        .serve_at(
+            PATH,
+            Brief {
+                config,
+                units,
+                events: events.clone(),
+            },
+        )?
+        .build()?;
+    events.attach(conn.clone());
+    Ok(conn)
+}
+
+struct Brief {
+    config: Config,
+    units: Supervisor,
+    events: Journal,
+}
+
+#[interface(name = "pink.wah.Brief1")]
+impl Brief {
+    /// Run a command in the box and return its PID
+    async fn run(
+        &self,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+        argv: Vec<String>,
+        env: HashMap<String, String>,
+        cwd: String,
+    ) -> fdo::Result<u32> {
+        refuse_sandboxed(&header, conn).await?;
+        let cwd = if cwd.is_empty() { "/" } else { &cwd };
+        self.spawn(ctxt, "dbus", argv, env, Path::new(cwd), &[])
+    }
+
+    /// Start a command detached from the caller as the job `name`, with its
+    /// output going to the job's log, and return its PID. Options are
+    /// `clean-env` (b) to not forward the host's environment, `unset` (as)
+    /// for variables to remove, `limits` (as) of `NAME=VALUE` resource
+    /// limits on top of the box's, `seccomp` (s) for the seccomp profile
+    /// instead of the box's and `fs-allow` (as) of `PATH:ro` or `PATH:rw` to
+    /// restrict the job to with Landlock. The profile can't let through what
+    /// the box's profile denies.
+    #[allow(clippy::too_many_arguments)]
+    async fn start_job(
+        &self,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+        name: String,
+        argv: Vec<String>,
+        env: HashMap<String, String>,
+        cwd: String,
+        options: HashMap<String, OwnedValue>,
+    ) -> fdo::Result<u32> {
+        refuse_sandboxed(&header, conn).await?;
+        if !jobs::is_valid_name(&name) {
+            return Err(fdo::Error::InvalidArgs(format!(
+                "Invalid job name '{}'",
+                name
+            )));
+        }
+        if Job::load(&self.config, &name).map(|job| job.is_running()) == Some(true) {
+            return Err(fdo::Error::Failed(format!(
+                "Job '{}' is already running",
+                name
+            )));
+        }
+        let Some((program, args)) = argv.split_first() else {
+            return Err(fdo::Error::InvalidArgs(String::from("Empty command")));
+        };
+
+        let clean_env = options
+            .get("clean-env")
+            .and_then(|x| bool::try_from(x).ok())
+            .unwrap_or(false);
+        let unset = options
+            .get("unset")
+            .and_then(|x| Vec::<String>::try_from(x.try_clone().ok()?).ok())
+            .unwrap_or_default();
+        // Invalid box settings were reported when the service started
+        let mut limits = Limits::from_settings(&self.config.settings).unwrap_or_default();
+        let assignments = options
+            .get("limits")
+            .and_then(|x| Vec::<String>::try_from(x.try_clone().ok()?).ok())
+            .unwrap_or_default();
+        for assignment in assignments {
+            let (limit, value) =
+                limits::parse_assignment(&assignment).map_err(fdo::Error::InvalidArgs)?;
+            limits.set(limit, value);
+        }
+        limits.check().map_err(fdo::Error::InvalidArgs)?;
+        let profile = options
+            .get("seccomp")
+            .and_then(|x| <&str>::try_from(x).ok());
+        let profile = seccomp::profile_for(&self.config.settings, profile)
+            .map_err(fdo::Error::InvalidArgs)?;
+        let default =
+            seccomp::profile_for(&self.config.settings, None).map_err(fdo::Error::Failed)?;
+        if let Some(default) = default {
+            if !profile.as_ref().is_some_and(|x| x.covers(&default)) {
+                return Err(fdo::Error::InvalidArgs(format!(
+                    "The seccomp profile lets through calls that the box's '{}' denies",
+                    default.name
+                )));
+            }
+        }
+        let rules = options
+            .get("fs-allow")
+            .and_then(|x| Vec::<String>::try_from(x.try_clone().ok()?).ok())
+            .unwrap_or_default()
+            .iter()
+            .map(|x| landlock::parse_rule(x))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(fdo::Error::InvalidArgs)?;
+
+        let log = jobs::create_log(&self.config, &name)
+            .map_err(|err| fdo::Error::IOError(format!("Could not create log: {}", err)))?;
+        let mut command = match clean_env {
+            true => clean_command(&self.config, program, args, env),
+            false => command(&self.config, program, args, env),
+        };
+        for key in unset {
+            command.env_remove(key);
+        }
+        limits.apply_to(&mut command);
+        landlock::apply_to(&rules, &mut command).map_err(fdo::Error::Failed)?;
+        if let Some(profile) = profile {
+            profile
+                .apply_to(
+                    &mut command,
+                    "job",
+                    Some(Reporter::Journal(self.events.clone())),
+                )
+                .map_err(|err| fdo::Error::Failed(format!("Could not set up seccomp: {}", err)))?;
+        }
+        command
+            .current_dir(if cwd.is_empty() { "/" } else { &cwd })
+            .stdout(
+                log.try_clone()
+                    .map_err(|err| fdo::Error::IOError(err.to_string()))?,
+            )
+            .stderr(log)
+            .process_group(0);
+
+        self.start(ctxt, command, "job", argv, Some(&name))
+    }
+
+    /// Applications in the box as (id, name, exec, comment)
+    fn list_apps(&self) -> Vec<(String, String, String, String)> {
+        app::find_all(&self.config)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|app| (app.id, app.name, app.exec, app.comment.unwrap_or_default()))
+            .collect()
+    }
+
+    /// Launch an application in the box with optional files or URLs, and
+    /// return its PID. The application is held to the paths of its
+    /// `FsAllow` setting, as when it is run from the host.
+    async fn launch_app(
+        &self,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+        id: String,
+        uris: Vec<String>,
+    ) -> fdo::Result<u32> {
+        refuse_sandboxed(&header, conn).await?;
+        let apps = app::find_all(&self.config).unwrap_or_default();
+        let Some(app) = apps.iter().find(|app| app.id == id) else {
+            return Err(fdo::Error::InvalidArgs(format!("No application '{}'", id)));
+        };
+        let argv = parse_exec(&app.exec, &uris)
+            .ok_or_else(|| fdo::Error::Failed(format!("Invalid Exec key in '{}'", id)))?;
+
+        let rules = landlock::app_rules(&self.config.settings, &id).map_err(fdo::Error::Failed)?;
+
+        let home = std::env::var("HOME").unwrap_or_else(|_| String::from("/"));
+        let pid = self.spawn(ctxt, "app", argv, HashMap::new(), Path::new(&home), &rules)?;
+        self.events
+            .record(&Event::new("app-launched").with("id", id).with("pid", pid));
+        Ok(pid)
+    }
+
+    /// The box's name, the PID of the service and the (pid, cmdline) of
+    /// every process in the box
+    fn status(&self) -> fdo::Result<(String, u32, Processes)> {
+        let service = Service::from_existing()
+            .ok_or_else(|| fdo::Error::Failed(String::from("nixbox not running")))?;
+        let processes = box_processes(service.pid)
+            .ok_or_else(|| fdo::Error::Failed(String::from("nixbox not running")))?;
+
+        Ok((
+            String::from(BOX_NAME),
+            service.pid as u32,
+            processes
+                .into_iter()
+                .map(|(pid, cmdline)| (pid as u32, cmdline))
+                .collect(),
+        ))
+    }
+
+    /// Start the systemd user unit `name`
+    fn start_unit(&self, name: String) -> fdo::Result<()> {
+        self.units.start(&name).map_err(fdo::Error::Failed)
+    }
+
+    /// Stop the systemd user unit `name`
+    fn stop_unit(&self, name: String) -> fdo::Result<()> {
+        self.units.stop(&name).map_err(fdo::Error::Failed)
+    }
+
+    /// The box's units as (name, description, path, enabled, status, pid),
+    /// where the PID is 0 when the unit isn't running
+    fn list_units(&self) -> Vec<UnitInfo> {
+        self.units.list()
+    }
+
+    /// Record the event `name` with `fields` of strings and numbers, and
+    /// broadcast it as the `Event` signal. Events are reported from the host,
+    /// so calls from processes in the box are refused.
+    async fn emit(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] conn: &zbus::Connection,
+        name: String,
+        fields: HashMap<String, OwnedValue>,
+    ) -> fdo::Result<()> {
+        if in_box(caller_pid(&header, conn).await?) {
+            return Err(fdo::Error::AccessDenied(String::from(
+                "Events can't be emitted from the box",
+            )));
+        }
+        if !events::is_valid_name(&name) {
+            return Err(fdo::Error::InvalidArgs(format!(
+                "Invalid event name '{}'",
+                name
+            )));
+        }
+        let mut event = Event::new(&name);
+        let mut keys: Vec<&String> = fields.keys().collect();
+        keys.sort();
+        for key in keys {
+            let value = &fields[key];
+            let field = match value.value_signature().as_str() {
+                "s" => <&str>::try_from(value).ok().map(Field::from),
+                "x" => i64::try_from(value).ok().map(Field::Number),
+                "i" => i32::try_from(value).ok().map(Field::from),
+                "u" => u32::try_from(value).ok().map(Field::from),
+                "t" => u64::try_from(value).ok().map(Field::Bytes),
+                "d" => f64::try_from(value).ok().map(Field::Float),
+                _ => None,
+            };
+            let Some(field) = field else {
+                return Err(fdo::Error::InvalidArgs(format!(
+                    "Field '{}' is not a string or number",
+                    key
+                )));
+            };
+            event = event.with(key, field);
+        }
+        self.events.record(&event);
+        Ok(())
+    }
+
+    /// The latest events as JSON objects, oldest first
+    fn list_events(&self) -> Vec<String> {
+        self.events.recent()
+    }
+
+    /// Stop the service
+    fn stop(&self) -> fdo::Result<()> {
+        let service = Service::from_existing()
+            .ok_or_else(|| fdo::Error::Failed(String::from("nixbox not running")))?;
+        service
+            .stop()
+            .map_err(|err| fdo::Error::Failed(format!("Could not stop nixbox: {}", err)))
+    }
+
+    #[zbus(signal)]
+    async fn process_started(
+        ctxt: &SignalContext<'_>,
+        pid: u32,
+        argv: Vec<String>,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn process_exited(ctxt: &SignalContext<'_>, pid: u32, code: i32) -> zbus::Result<()>;
+
+    /// An event recorded by the service, as a JSON object
+    #[zbus(signal)]
+    async fn event(ctxt: &SignalContext<'_>, json: String) -> zbus::Result<()>;
+}
+
+impl Brief {
+    fn spawn(
+        &self,
+        ctxt: SignalContext<'_>,
+        session: &str,
+        argv: Vec<String>,
+        env: HashMap<String, String>,
+        cwd: &Path,
+        rules: &[landlock::Rule],
+    ) -> fdo::Result<u32> {
+        let Some((program, args)) = argv.split_first() else {
+            return Err(fdo::Error::InvalidArgs(String::from("Empty command")));
+        };
+
+        let mut command = command(&self.config, program, args, env);
+        command.current_dir(cwd);
+        Limits::from_settings(&self.config.settings)
+            .unwrap_or_default()
+            .apply_to(&mut command);
+        landlock::apply_to(rules, &mut command).map_err(fdo::Error::Failed)?;
+        let profile =
+            seccomp::profile_for(&self.config.settings, None).map_err(fdo::Error::Failed)?;
+        if let Some(profile) = profile {
+            profile
+                .apply_to(
+                    &mut command,
+                    session,
+                    Some(Reporter::Journal(self.events.clone())),
+                )
+                .map_err(|err| fdo::Error::Failed(format!("Could not set up seccomp: {}", err)))?;
+        }
+        self.start(ctxt, command, session, argv, None)
+    }
+
+    /// Spawn `command` and signal when it starts and exits. When it is the
+    /// job `job`, the job and its exit code are recorded. The command goes in
+    /// the audit log as started by `session`.
+    fn start(
+        &self,
+        ctxt: SignalContext<'_>,
+        mut command: Command,
+        session: &str,
+        argv: Vec<String>,
+        job: Option<&str>,
+    ) -> fdo::Result<u32> {
+        let mut audit = audit::Entry::start(&self.config, session, &argv);
+        let child = command
+            .stdin(Stdio::null())
+            .spawn()
+            .map_err(|err| fdo::Error::SpawnFailed(format!("{}: {}", argv[0], err)))?;
+        let pid = child.id();
+        audit.begin(command.get_current_dir().unwrap_or(Path::new("/")));
+
+        let job = job.map(|name| Job::new(&self.config, name, pid, &argv));
+        if let Some(Err(err)) = job.as_ref().map(Job::save) {
+            eprintln!("Could not save job: {}", err);
+        }
+        let job_name = job.as_ref().map(|x| x.name.clone());
+        self.events.record(
+            &Event::new("process-started")
+                .with("pid", pid)
+                .with("command", argv.join(" "))
+                .with("job", job_name.clone()),
+        );
+
+        let ctxt = ctxt.into_owned();
+        let started = ctxt.clone();
+        let executor = ctxt.connection().executor().clone();
+        executor
+            .spawn(
+                async move { Self::process_started(&started, pid, argv).await },
+                "process-started",
+            )
+            .detach();
+
+        let events = self.events.clone();
+        thread::spawn(move || {
+            let code = wait(ctxt, child);
+            audit.finish(code);
+            events.record(
+                &Event::new("process-exited")
+                    .with("pid", pid)
+                    .with("exit_code", code)
+                    .with("job", job_name),
+            );
+            if let Some(mut job) = job {
+                job.exit_code = Some(code);
+                let _ = job.save();
+            }
+        });
+        Ok(pid)
+    }
+}
+
+fn wait(ctxt: SignalContext<'static>, mut child: Child) -> i32 {
+    let pid = child.id();
+    let code = child.wait().map(exit_code).unwrap_or(-1);
+
+    let executor = ctxt.connection().executor().clone();
+    executor
+        .spawn(
+            async move { Brief::process_exited(&ctxt, pid, code).await },
+            "process-exited",
+        )
+        .detach();
+    code
+}
+
+/// PID of the process that sent the call with `header`
+async fn caller_pid(header: &Header<'_>, conn: &zbus::Connection) -> fdo::Result<i32> {
+    let Some(sender) = header.sender() else {
+        return Err(fdo::Error::AccessDenied(String::from("Unknown sender")));
+    };
+    let pid = fdo::DBusProxy::new(conn)
+        .await?
+        .get_connection_unix_process_id(sender.clone().into())
+        .await?;
+    Ok(pid as i32)
+}
+
+/// Refuse calls from sandboxed sessions, which could otherwise have the
+/// service start processes for them without their seccomp filter
+async fn refuse_sandboxed(header: &Header<'_>, conn: &zbus::Connection) -> fdo::Result<()> {
+    let pid = caller_pid(header, conn).await?;
+    let sandboxed = fs::read_to_string(format!("/proc/{}/status", pid))
+        .map(|status| host_exec::is_sandboxed(&status))
+        .unwrap_or(true);
+    match sandboxed {
+        true => Err(fdo::Error::AccessDenied(String::from(
+            "Not allowed from a sandboxed session",
+        ))),
+        false => Ok(()),
+    }
+}
+
+/// Whether `pid` is in the box with the service, in its mount namespace or
+/// its user namespace. Namespaces of host processes can't be seen from the
+/// box, so they never match.
+fn in_box(pid: i32) -> bool {
+    let own_pid = std::process::id() as i32;
+    ["mnt", "user"].iter().any(|ns| {
+        procs::namespace_id(pid, ns).is_some()
+            && procs::namespace_id(pid, ns) == procs::namespace_id(own_pid, ns)
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::Command;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use testdir::testdir;
+    use zbus::blocking::Proxy;
+    use zbus::zvariant::Value;
+
+    /// A private session bus which is stopped when dropped
+    pub(crate) struct DbusDaemon {
+        child: Child,
+        pub(crate) address: String,
+    }
+
+    impl DbusDaemon {
+        pub(crate) fn start() -> Option<Self> {
+            let mut child = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .ok()?;
+
+            let mut address = String::new();
+            BufReader::new(child.stdout.take()?)
+                .read_line(&mut address)
+                .ok()?;
+            Some(Self {
+                child,
+                address: address.trim().to_string(),
+            })
+        }
+
+        pub(crate) fn builder(&self) -> Builder<'static> {
+            Builder::address(self.address.as_str()).unwrap()
+        }
+    }
+
+    impl Drop for DbusDaemon {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    #[test]
+    fn it_serves_on_a_private_bus() {
+        let Some(daemon) = DbusDaemon::start() else {
+            eprintln!("dbus-daemon not found, skipping");
+            return;
+        };
+
+        let config = Config::for_tests(testdir!()).unwrap();
+        let events = Journal::new(testdir!().join("events.jsonl"));
+        let _service = build(
+            daemon.builder(),
+            config.clone(),
+            Supervisor::new(config),
+            events,
+        )
+        .unwrap();
+
+        let conn = daemon.builder().build().unwrap();
+        let proxy = Proxy::new(&conn, NAME, PATH, NAME).unwrap();
+
+        let apps: Vec<(String, String, String, String)> = proxy.call("ListApps", &()).unwrap();
+        assert!(apps.is_empty());
+
+        let (tx, rx) = mpsc::channel();
+        let signals = proxy.receive_signal("ProcessExited").unwrap();
+        thread::spawn(move || {
+            for msg in signals {
+                let _ = tx.send(msg.body().deserialize::<(u32, i32)>().unwrap());
+            }
+        });
+
+        let env: HashMap<String, String> = HashMap::new();
+        let pid: u32 = proxy
+            .call("Run", &(vec!["sh", "-c", "exit 3"], &env, "/"))
+            .unwrap();
+        let exited = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(exited, (pid, 3));
+
+        let empty: Vec<String> = vec![];
+        let result: zbus::Result<u32> = proxy.call("Run", &(empty, &env, "/"));
+        assert!(result.is_err());
+
+        // The test shares the service's namespaces, as processes in the box do
+        let fields: HashMap<&str, Value> = HashMap::from([("exit_code", Value::from(2))]);
+        let result: zbus::Result<()> = proxy.call("Emit", &("session-exited", &fields));
+        assert!(result.is_err());
+        let events: Vec<String> = proxy.call("ListEvents", &()).unwrap();
+        assert!(events[0].starts_with(r#"{"event":"process-started","#));
+        assert!(!events.last().unwrap().ends_with(r#""exit_code":2}"#));
+    }
+
+    #[test]
+    fn it_records_jobs() {
+        let Some(daemon) = DbusDaemon::start() else {
+            eprintln!("dbus-daemon not found, skipping");
+            return;
+        };
+
+        let mut config = Config::for_tests(testdir!()).unwrap();
+        config
+            .env
+            .insert("XDG_STATE_HOME".into(), testdir!().into_os_string());
+        let _service = build(
+            daemon.builder(),
+            config.clone(),
+            Supervisor::new(config.clone()),
+            Journal::new(testdir!().join("events.jsonl")),
+        )
+        .unwrap();
+
+        let conn = daemon.builder().build().unwrap();
+        let proxy = Proxy::new(&conn, NAME, PATH, NAME).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let signals = proxy.receive_signal("ProcessExited").unwrap();
+        thread::spawn(move || {
+            for msg in signals {
+                let _ = tx.send(msg.body().deserialize::<(u32, i32)>().unwrap());
+            }
+        });
+
+        let env: HashMap<String, String> = HashMap::new();
+        let options: HashMap<String, OwnedValue> = HashMap::new();
+        let argv = vec!["sh", "-c", "echo hello; exit 4"];
+        let pid: u32 = proxy
+            .call("StartJob", &("web", &argv, &env, "/", &options))
+            .unwrap();
+        let exited = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(exited, (pid, 4));
+
+        // The job file is written by the thread that waited for the job
+        let mut job = Job::load(&config, "web").unwrap();
+        for _ in 0..100 {
+            if job.exit_code.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+            job = Job::load(&config, "web").unwrap();
+        }
+        assert_eq!(job.pid, pid);
+        assert_eq!(job.exit_code, Some(4));
+        assert_eq!(std::fs::read_to_string(job.log_path()).unwrap(), "hello\n");
+
+        let result: zbus::Result<u32> =
+            proxy.call("StartJob", &("../x", &argv, &env, "/", &options));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn it_refuses_sandboxed_callers() {
+        let Some(daemon) = DbusDaemon::start() else {
+            eprintln!("dbus-daemon not found, skipping");
+            return;
+        };
+
+        let config = Config::for_tests(testdir!()).unwrap();
+        let _service = build(
+            daemon.builder(),
+            config.clone(),
+            Supervisor::new(config),
+            Journal::new(testdir!().join("events.jsonl")),
+        )
+        .unwrap();
+
+        // As a `--fs-allow` session, which can't gain privileges
+        let launch = |sandboxed: bool| {
+            let mut command = Command::new("dbus-send");
+            command.args([
+                &format!("--bus={}", daemon.address),
+                "--print-reply",
+                &format!("--dest={}", NAME),
+                PATH,
+                &format!("{}.LaunchApp", NAME),
+                "string:missing",
+                "array:string:x",
+            ]);
+            if sandboxed {
+                unsafe {
+                    command.pre_exec(
+                        || match libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) {
+                            0 => Ok(()),
+                            _ => Err(std::io::Error::last_os_error()),
+                        },
+                    );
+                }
+            }
+            let output = command.output().ok()?;
+            Some(String::from_utf8_lossy(&output.stderr).into_owned())
+        };
+
+        let Some(error) = launch(false) else {
+            eprintln!("dbus-send not found, skipping");
+            return;
+        };
+        assert!(error.contains("InvalidArgs"));
+        assert!(launch(true).unwrap().contains("AccessDenied"));
+    }
+}
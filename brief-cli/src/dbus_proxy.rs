@@ -0,0 +1,759 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::{TryFrom, TryInto};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, BufRead, BufReader, IoSlice, IoSliceMut, Read, Write};
+use std::net::Shutdown;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::{SocketAddr, UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use nix::mount::{mount, MsFlags};
+use nix::sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags};
+use zbus::message::{EndianSig, Flags, Message, Type};
+use zbus::zvariant::serialized::{Context, Data};
+use zbus::zvariant::Endian;
+
+use crate::util::KeyFile;
+
+const BUS_NAME: &str = "org.freedesktop.DBus";
+const ACCESS_DENIED: &str = "org.freedesktop.DBus.Error.AccessDenied";
+
+/// Largest message the D-Bus specification allows
+const MAX_MESSAGE_SIZE: usize = 128 * 1024 * 1024;
+
+/// Most file descriptors the kernel passes in one message
+const MAX_FDS: usize = 253;
+
+/// Names on the host's session bus that the box may use, from the
+/// `[DBusProxy]` group of `brief.conf`. Each pattern is either a bus name or
+/// a name ending in `.*`, which matches the name and all names below it.
+/// Owning a name implies talking to it, and talking implies seeing it.
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    talk: Vec<String>,
+    own: Vec<String>,
+    see: Vec<String>,
+}
+
+impl Policy {
+    pub fn from_settings(settings: &KeyFile) -> Self {
+        let talk = match settings.get("DBusProxy", "Talk") {
+            Some(_) => settings.get_list("DBusProxy", "Talk"),
+            None => vec![String::from("org.freedesktop.portal.*")],
+        };
+        Self {
+            talk,
+            own: settings.get_list("DBusProxy", "Own"),
+            see: settings.get_list("DBusProxy", "See"),
+        }
+    }
+
+    fn can_own(&self, name: &str) -> bool {
+        matches(&self.own, name)
+    }
+
+    fn can_talk(&self, name: &str) -> bool {
+        self.can_own(name) || matches(&self.talk, name)
+    }
+
+    fn can_see(&self, name: &str) -> bool {
+        self.can_talk(name) || matches(&self.see, name)
+    }
+}
+
+fn matches(patterns: &[String], name: &str) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| match pattern.strip_suffix(".*") {
+            Some(prefix) => {
+                name == prefix
+                    || name
+                        .strip_prefix(prefix)
+                        .map(|rest| rest.starts_with('.'))
+                        .unwrap_or(false)
+            }
+            None => name == pattern,
+        })
+}
+
+/// Listen on `path` and forward every connection to the bus at `address`,
+/// filtered by `policy`. Connections are served on background threads.
+pub fn spawn(address: &str, path: &Path, policy: Policy) -> io::Result<()> {
+    let mut upstream = parse_address(address).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported bus address '{}'", address),
+        )
+    })?;
+    // Connect through a handle on the socket, which still reaches it once
+    // `hide` covered its path
+    let handle = match upstream.as_pathname() {
+        Some(socket) => {
+            let handle = fs::File::options()
+                .read(true)
+                .custom_flags(libc::O_PATH)
+                .open(socket)?;
+            upstream = SocketAddr::from_pathname(format!("/proc/self/fd/{}", handle.as_raw_fd()))?;
+            Some(handle)
+        }
+        None => None,
+    };
+
+    if path.symlink_metadata().is_ok() {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    let policy = Arc::new(policy);
+
+    thread::spawn(move || {
+        let _handle = handle;
+        for client in listener.incoming() {
+            let Ok(client) = client else {
+                continue;
+            };
+            let upstream = upstream.clone();
+            let policy = policy.clone();
+            thread::spawn(move || {
+                if let Err(err) = proxy(client, &upstream, policy) {
+                    eprintln!("D-Bus proxy connection closed: {}", err);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Cover the socket of the bus at `address` in our mount namespace, so that
+/// processes in the box can't get around the proxy by ignoring
+/// `DBUS_SESSION_BUS_ADDRESS`. A bus on an abstract socket can't be hidden.
+pub fn hide(address: &str) -> io::Result<()> {
+    let Some(socket) = parse_address(address).and_then(|x| x.as_pathname().map(Path::to_path_buf))
+    else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("'{}' is not a socket path", address),
+        ));
+    };
+    mount(
+        Some("/dev/null"),
+        &socket,
+        None::<&str>,
+        MsFlags::MS_BIND,
+        None::<&str>,
+    )?;
+    Ok(())
+}
+
+/// Socket address of the first `unix:` transport in a D-Bus address
+fn parse_address(address: &str) -> Option<SocketAddr> {
+    for transport in address.split(';') {
+        let Some(params) = transport.strip_prefix("unix:") else {
+            continue;
+        };
+        for param in params.split(',') {
+            match param.split_once('=') {
+                Some(("path", path)) => {
+                    return SocketAddr::from_pathname(OsStr::from_bytes(&unescape(path)?)).ok()
+                }
+                Some(("abstract", name)) => {
+                    return SocketAddr::from_abstract_name(unescape(name)?).ok()
+                }
+                _ => (),
+            }
+        }
+    }
+    None
+}
+
+/// Undo the `%xx` escaping of D-Bus address values
+fn unescape(val: &str) -> Option<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut iter = val.bytes();
+    while let Some(byte) = iter.next() {
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let hex = [iter.next()?, iter.next()?];
+        bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+    }
+    Some(bytes)
+}
+
+/// Calls from the client whose replies the proxy needs to look at
+enum Pending {
+    /// Call to a well-known name. The sender of the reply may be talked to.
+    Peer,
+    /// `GetNameOwner` of a name the client may talk to
+    NameOwner,
+    /// `ListNames` or `ListActivatableNames`, whose reply is filtered
+    ListNames(Message),
+}
+
+#[derive(Default)]
+struct State {
+    /// Unique names of connections that own names the client may talk to
+    peers: HashSet<String>,
+    /// Well-known names the client owns, which anyone may call
+    owned: HashSet<String>,
+    /// Outstanding calls by serial number
+    pending: HashMap<u32, Pending>,
+    /// Serial numbers of the client's calls that wait for a reply, the only
+    /// replies it gets
+    calls: HashSet<u32>,
+}
+
+fn proxy(client: UnixStream, upstream: &SocketAddr, policy: Arc<Policy>) -> io::Result<()> {
+    let bus = UnixStream::connect_addr(upstream)?;
+
+    let mut client_reader = BufReader::new(Socket::new(client.try_clone()?));
+    let mut bus_reader = BufReader::new(Socket::new(bus.try_clone()?));
+    let mut client_writer = client.try_clone()?;
+    let mut bus_writer = bus.try_clone()?;
+    authenticate(
+        &mut client_reader,
+        &mut client_writer,
+        &mut bus_reader,
+        &mut bus_writer,
+    )?;
+
+    let state = Arc::new(Mutex::new(State::default()));
+    let client_writer = Arc::new(Mutex::new(client_writer));
+
+    let from_bus = {
+        let client_writer = client_writer.clone();
+        let state = state.clone();
+        let policy = policy.clone();
+        thread::spawn(move || relay_from_bus(bus_reader, &client_writer, &state, &policy))
+    };
+    let result = relay_from_client(client_reader, bus_writer, &client_writer, &state, &policy);
+
+    // Closing both ends stops the other direction as well
+    let _ = client.shutdown(Shutdown::Both);
+    let _ = bus.shutdown(Shutdown::Both);
+    let _ = from_bus.join();
+    result
+}
+
+/// Relay the SASL handshake line by line until the client sends `BEGIN`
+fn authenticate(
+    client_reader: &mut BufReader<Socket>,
+    client: &mut UnixStream,
+    bus_reader: &mut BufReader<Socket>,
+    bus: &mut UnixStream,
+) -> io::Result<()> {
+    let mut nul = [0u8; 1];
+    client_reader.read_exact(&mut nul)?;
+    bus.write_all(&nul)?;
+
+    loop {
+        let mut line = String::new();
+        if client_reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if line.starts_with("BEGIN") {
+            return bus.write_all(line.as_bytes());
+        }
+
+        bus.write_all(line.as_bytes())?;
+        let mut reply = String::new();
+        if bus_reader.read_line(&mut reply)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        client.write_all(reply.as_bytes())?;
+    }
+}
+
+fn relay_from_client(
+    mut reader: BufReader<Socket>,
+    mut bus: UnixStream,
+    client: &Mutex<UnixStream>,
+    state: &Mutex<State>,
+    policy: &Policy,
+) -> io::Result<()> {
+    while let Some(msg) = read_message(&mut reader)? {
+        let header = msg.header();
+        if msg.message_type() == Type::Signal {
+            // Broadcasts are fine, unicast signals only go where calls may
+            if let Some(dest) = header.destination() {
+                let state = state.lock().unwrap();
+                if dest.as_str() != BUS_NAME
+                    && !state.peers.contains(dest.as_str())
+                    && (dest.as_str().starts_with(':') || !policy.can_talk(dest))
+                {
+                    continue;
+                }
+            }
+        }
+        if msg.message_type() == Type::MethodCall {
+            let checked = check_call(&msg, policy, &state.lock().unwrap());
+            let serial = header.primary().serial_num().get();
+            if checked.is_ok() && !header.primary().flags().contains(Flags::NoReplyExpected) {
+                state.lock().unwrap().calls.insert(serial);
+            }
+            match checked {
+                Ok(Some(pending)) => {
+                    state.lock().unwrap().pending.insert(serial, pending);
+                }
+                Ok(None) => (),
+                Err(reason) => {
+                    if !header.primary().flags().contains(Flags::NoReplyExpected) {
+                        let reply = Message::method_error(&msg, ACCESS_DENIED)
+                            .and_then(|x| x.sender(BUS_NAME))
+                            .and_then(|x| x.build(&reason))
+                            .map_err(invalid_data)?;
+                        write_message(&mut client.lock().unwrap(), &reply)?;
+                    }
+                    continue;
+                }
+            }
+        }
+        write_message(&mut bus, &msg)?;
+    }
+    Ok(())
+}
+
+/// Decide whether the client may make a method call. Calls whose replies
+/// need to be inspected are returned as `Pending`.
+fn check_call(msg: &Message, policy: &Policy, state: &State) -> Result<Option<Pending>, String> {
+    let header = msg.header();
+    let Some(dest) = header.destination().map(|x| x.to_string()) else {
+        return Ok(None);
+    };
+    let member = header.member().map(|x| x.to_string()).unwrap_or_default();
+
+    if dest == BUS_NAME {
+        return match member.as_str() {
+            "RequestName" | "ReleaseName" => match first_arg(msg) {
+                Some(name) if policy.can_own(&name) => Ok(None),
+                name => Err(format!("Not allowed to own '{}'", name.unwrap_or_default())),
+            },
+            "GetNameOwner" | "NameHasOwner" | "StartServiceByName" => match first_arg(msg) {
+                Some(name) if name.starts_with(':') || policy.can_talk(&name) => {
+                    Ok(Some(Pending::NameOwner).filter(|_| member == "GetNameOwner"))
+                }
+                Some(name) if member == "NameHasOwner" && policy.can_see(&name) => Ok(None),
+                name => Err(format!("Not allowed to see '{}'", name.unwrap_or_default())),
+            },
+            "ListNames" | "ListActivatableNames" => Ok(Some(Pending::ListNames(msg.clone()))),
+            "AddMatch" => match msg.body().deserialize::<String>() {
+                Ok(rule) if !is_eavesdropping(&rule) => Ok(None),
+                _ => Err(String::from("Not allowed to eavesdrop")),
+            },
+            "BecomeMonitor" | "UpdateActivationEnvironment" => {
+                Err(format!("Not allowed to call '{}'", member))
+            }
+            _ => Ok(None),
+        };
+    }
+
+    if dest.starts_with(':') {
+        return match state.peers.contains(&dest) {
+            true => Ok(None),
+            false => Err(format!("Not allowed to talk to '{}'", dest)),
+        };
+    }
+    match policy.can_talk(&dest) {
+        true => Ok(Some(Pending::Peer)),
+        false => Err(format!("Not allowed to talk to '{}'", dest)),
+    }
+}
+
+fn relay_from_bus(
+    mut reader: BufReader<Socket>,
+    client: &Mutex<UnixStream>,
+    state: &Mutex<State>,
+    policy: &Policy,
+) -> io::Result<()> {
+    while let Some(msg) = read_message(&mut reader)? {
+        let header = msg.header();
+        let sender = header.sender().map(|x| x.to_string()).unwrap_or_default();
+
+        match msg.message_type() {
+            Type::MethodReturn | Type::Error => {
+                let Some(serial) = header.reply_serial().map(|x| x.get()) else {
+                    continue;
+                };
+                if !state.lock().unwrap().calls.remove(&serial) {
+                    continue;
+                }
+                let pending = state.lock().unwrap().pending.remove(&serial);
+                match pending {
+                    Some(Pending::Peer) => {
+                        state.lock().unwrap().peers.insert(sender);
+                    }
+                    Some(Pending::NameOwner) if msg.message_type() == Type::MethodReturn => {
+                        if let Ok(owner) = msg.body().deserialize::<String>() {
+                            state.lock().unwrap().peers.insert(owner);
+                        }
+                    }
+                    Some(Pending::ListNames(call)) if msg.message_type() == Type::MethodReturn => {
+                        let reply = filter_names(&msg, &call, policy, &state.lock().unwrap())
+                            .map_err(invalid_data)?;
+                        write_message(&mut client.lock().unwrap(), &reply)?;
+                        continue;
+                    }
+                    _ => (),
+                }
+            }
+            Type::Signal if sender == BUS_NAME => {
+                let member = header.member().map(|x| x.as_str());
+                if member == Some("NameAcquired") || member == Some("NameLost") {
+                    if let Ok(name) = msg.body().deserialize::<String>() {
+                        let owned = &mut state.lock().unwrap().owned;
+                        match member == Some("NameAcquired") {
+                            true => owned.insert(name),
+                            false => owned.remove(&name),
+                        };
+                    }
+                } else if member == Some("NameOwnerChanged") {
+                    let Ok((name, _, owner)) = msg.body().deserialize::<(String, String, String)>()
+                    else {
+                        continue;
+                    };
+                    if !name.starts_with(':') && !policy.can_see(&name) {
+                        continue;
+                    }
+                    if !owner.is_empty() && policy.can_talk(&name) {
+                        state.lock().unwrap().peers.insert(owner);
+                    }
+                }
+            }
+            // Only services the client may talk to can reach it, besides
+            // callers of the names it owns
+            Type::Signal | Type::MethodCall => {
+                let state = state.lock().unwrap();
+                let to_owned = msg.message_type() == Type::MethodCall
+                    && header
+                        .destination()
+                        .is_some_and(|x| state.owned.contains(x.as_str()));
+                if !to_owned && !state.peers.contains(&sender) {
+                    continue;
+                }
+            }
+        }
+        write_message(&mut client.lock().unwrap(), &msg)?;
+    }
+    Ok(())
+}
+
+/// Rebuild a `ListNames` reply with only the names the client may see
+fn filter_names(
+    reply: &Message,
+    call: &Message,
+    policy: &Policy,
+    state: &State,
+) -> zbus::Result<Message> {
+    let names: Vec<String> = reply.body().deserialize()?;
+    let names: Vec<String> = names
+        .into_iter()
+        .filter(|name| match name.starts_with(':') {
+            true => state.peers.contains(name),
+            false => name == BUS_NAME || policy.can_see(name),
+        })
+        .collect();
+    Message::method_reply(call)?.sender(BUS_NAME)?.build(&names)
+}
+
+/// Whether the match rule `rule` asks for messages meant for others
+fn is_eavesdropping(rule: &str) -> bool {
+    let mut quoted = false;
+    rule.split(|c| {
+        if c == '\'' {
+            quoted = !quoted;
+        }
+        c == ',' && !quoted
+    })
+    .filter_map(|x| x.split_once('='))
+    .any(|(key, value)| key.trim() == "eavesdrop" && value.trim().trim_matches('\'') == "true")
+}
+
+fn first_arg(msg: &Message) -> Option<String> {
+    let body = msg.body();
+    body.deserialize::<(String, u32)>()
+        .map(|(name, _)| name)
+        .or_else(|_| body.deserialize::<String>())
+        .ok()
+}
+
+/// One end of a connection, which keeps the file descriptors that come
+/// with the bytes read from it until a message claims them
+struct Socket {
+    stream: UnixStream,
+    fds: VecDeque<OwnedFd>,
+}
+
+impl Socket {
+    fn new(stream: UnixStream) -> Self {
+        Self {
+            stream,
+            fds: VecDeque::new(),
+        }
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut iov = [IoSliceMut::new(buf)];
+        let mut cmsg = nix::cmsg_space!([RawFd; MAX_FDS]);
+        let msg = recvmsg::<()>(
+            self.stream.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        )?;
+        for cmsg in msg.cmsgs()? {
+            if let ControlMessageOwned::ScmRights(raw) = cmsg {
+                self.fds.extend(
+                    raw.into_iter()
+                        .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }),
+                );
+            }
+        }
+        Ok(msg.bytes)
+    }
+}
+
+/// Write a message and the file descriptors it carries
+fn write_message(stream: &mut UnixStream, msg: &Message) -> io::Result<()> {
+    let bytes = msg.data().bytes();
+    let fds: Vec<RawFd> = msg.data().fds().iter().map(|x| x.as_raw_fd()).collect();
+    if fds.is_empty() {
+        return stream.write_all(bytes);
+    }
+    let sent = sendmsg::<()>(
+        stream.as_raw_fd(),
+        &[IoSlice::new(bytes)],
+        &[ControlMessage::ScmRights(&fds)],
+        MsgFlags::empty(),
+        None,
+    )?;
+    stream.write_all(&bytes[sent..])
+}
+
+/// Read one message from the stream. Returns `None` at the end of the stream.
+fn read_message(reader: &mut BufReader<Socket>) -> io::Result<Option<Message>> {
+    let mut bytes = vec![0u8; 16];
+    match reader.read_exact(&mut bytes) {
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+
+    let endian = Endian::from(EndianSig::try_from(bytes[0]).map_err(invalid_data)?);
+    let read_u32 = |x: &[u8]| {
+        let x = x.try_into().unwrap();
+        match endian {
+            Endian::Little => u32::from_le_bytes(x),
+            Endian::Big => u32::from_be_bytes(x),
+        }
+    };
+    let body_len = read_u32(&bytes[4..8]) as usize;
+    let fields_len = read_u32(&bytes[12..16]) as usize;
+
+    // The header fields are padded to a multiple of 8 before the body
+    let header_len = (16 + fields_len + 7) & !7;
+    let len = header_len + body_len;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(invalid_data("message too large"));
+    }
+    bytes.resize(len, 0);
+    reader.read_exact(&mut bytes[16..])?;
+
+    // SAFETY: The file descriptors the message refers to are passed along,
+    // once we know how many there are
+    let context = Context::new_dbus(endian, 0);
+    let msg = unsafe { Message::from_bytes(Data::new(bytes, context)) }.map_err(invalid_data)?;
+    let count = msg.header().unix_fds().unwrap_or(0) as usize;
+    if count == 0 {
+        return Ok(Some(msg));
+    }
+    let fds = &mut reader.get_mut().fds;
+    if fds.len() < count {
+        return Err(invalid_data("missing file descriptors"));
+    }
+    let data = Data::new_fds(msg.data().bytes().to_vec(), context, fds.drain(..count));
+    let msg = unsafe { Message::from_bytes(data) };
+    msg.map(Some).map_err(invalid_data)
+}
+
+fn invalid_data(err: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::dbus::{self, tests::DbusDaemon};
+    use crate::events::Journal;
+    use crate::units::Supervisor;
+    use testdir::testdir;
+    use zbus::blocking::connection::Builder;
+    use zbus::blocking::fdo::DBusProxy;
+    use zbus::blocking::Proxy;
+
+    #[test]
+    fn it_matches_name_patterns() {
+        let policy = Policy {
+            talk: vec![String::from("org.freedesktop.portal.*")],
+            own: vec![String::from("org.example.App")],
+            see: vec![String::from("org.example.Seen")],
+        };
+
+        assert!(policy.can_talk("org.freedesktop.portal.Desktop"));
+        assert!(policy.can_talk("org.freedesktop.portal"));
+        assert!(!policy.can_talk("org.freedesktop.portalx"));
+        assert!(policy.can_talk("org.example.App"));
+        assert!(!policy.can_own("org.example.App.Child"));
+        assert!(policy.can_see("org.example.Seen"));
+        assert!(!policy.can_talk("org.example.Seen"));
+        assert!(!policy.can_see("org.freedesktop.secrets"));
+    }
+
+    #[test]
+    fn it_filters_a_private_bus() {
+        let Some(daemon) = DbusDaemon::start() else {
+            eprintln!("dbus-daemon not found, skipping");
+            return;
+        };
+
+        let config = Config::for_tests(testdir!()).unwrap();
+        let _service = dbus::build(
+            daemon.builder(),
+            config.clone(),
+            Supervisor::new(config),
+            Journal::new(testdir!().join("events.jsonl")),
+        )
+        .unwrap();
+        let _denied = daemon
+            .builder()
+            .name("org.example.Denied")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let socket = testdir!().join("bus");
+        let policy = Policy {
+            talk: vec![String::from(dbus::NAME)],
+            ..Policy::default()
+        };
+        spawn(&daemon.address, &socket, policy).unwrap();
+
+        let address = format!("unix:path={}", socket.display());
+        let conn = Builder::address(address.as_str()).unwrap().build().unwrap();
+
+        let proxy = Proxy::new(&conn, dbus::NAME, dbus::PATH, dbus::NAME).unwrap();
+        let apps: Vec<(String, String, String, String)> = proxy.call("ListApps", &()).unwrap();
+        assert!(apps.is_empty());
+
+        let denied = Proxy::new(&conn, "org.example.Denied", "/", "org.example.Denied").unwrap();
+        let result: zbus::Result<()> = denied.call("Ping", &());
+        assert!(matches!(
+            result,
+            Err(zbus::Error::MethodError(name, _, _)) if name.as_str() == ACCESS_DENIED
+        ));
+
+        let bus = DBusProxy::new(&conn).unwrap();
+        let names: Vec<String> = bus
+            .list_names()
+            .unwrap()
+            .into_iter()
+            .map(|x| x.to_string())
+            .collect();
+        assert!(names.iter().any(|x| x == dbus::NAME));
+        assert!(!names.iter().any(|x| x == "org.example.Denied"));
+
+        assert!(bus
+            .request_name("org.example.Mine".try_into().unwrap(), Default::default())
+            .is_err());
+        let add_match = |rule: &str| bus.inner().call::<_, _, ()>("AddMatch", &(rule,));
+        assert!(add_match("type='method_call',eavesdrop='true'").is_err());
+        assert!(add_match("type='signal',arg0='a,eavesdrop=true'").is_ok());
+    }
+
+    struct Echo;
+
+    #[zbus::interface(name = "org.example.Echo")]
+    impl Echo {
+        fn echo(&self, text: String) -> String {
+            text
+        }
+
+        fn read(&self, fd: zbus::zvariant::OwnedFd) -> String {
+            let mut text = String::new();
+            let _ = fs::File::from(OwnedFd::from(fd)).read_to_string(&mut text);
+            text
+        }
+    }
+
+    #[test]
+    fn it_relays_owned_names() {
+        let Some(daemon) = DbusDaemon::start() else {
+            eprintln!("dbus-daemon not found, skipping");
+            return;
+        };
+
+        let socket = testdir!().join("bus");
+        let policy = Policy {
+            own: vec![String::from("org.example.Echo")],
+            ..Policy::default()
+        };
+        spawn(&daemon.address, &socket, policy).unwrap();
+
+        let address = format!("unix:path={}", socket.display());
+        let _service = Builder::address(address.as_str())
+            .unwrap()
+            .name("org.example.Echo")
+            .unwrap()
+            .serve_at("/", Echo)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let host = daemon.builder().build().unwrap();
+        let proxy = Proxy::new(&host, "org.example.Echo", "/", "org.example.Echo").unwrap();
+        let reply: String = proxy.call("Echo", &("hello",)).unwrap();
+        assert_eq!(reply, "hello");
+    }
+
+    #[test]
+    fn it_passes_fds() {
+        let Some(daemon) = DbusDaemon::start() else {
+            eprintln!("dbus-daemon not found, skipping");
+            return;
+        };
+        let _service = daemon
+            .builder()
+            .name("org.example.Echo")
+            .unwrap()
+            .serve_at("/", Echo)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let socket = testdir!().join("bus");
+        let policy = Policy {
+            talk: vec![String::from("org.example.Echo")],
+            ..Policy::default()
+        };
+        spawn(&daemon.address, &socket, policy).unwrap();
+
+        let file = testdir!().join("file");
+        fs::write(&file, "hello").unwrap();
+        let file = fs::File::open(file).unwrap();
+
+        let address = format!("unix:path={}", socket.display());
+        let conn = Builder::address(address.as_str()).unwrap().build().unwrap();
+        let proxy = Proxy::new(&conn, "org.example.Echo", "/", "org.example.Echo").unwrap();
+        let text: String = proxy
+            .call("Read", &(zbus::zvariant::Fd::from(&file),))
+            .unwrap();
+        assert_eq!(text, "hello");
+    }
+}
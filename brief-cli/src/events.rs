@@ -0,0 +1,501 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use nix::fcntl::OFlag;
+use nix::unistd::{fork, getppid, pipe2, setsid, ForkResult};
+use zbus::blocking::{Connection, Proxy};
+use zbus::proxy::MethodFlags;
+use zbus::zvariant::Value;
+
+use crate::audit::{escape, unescape};
+use crate::config::Config;
+use crate::dbus;
+use crate::output::{json_string, json_value, Field};
+use crate::status::{parse_mountinfo, Mount};
+
+/// Events the service keeps in memory for `brief events`
+const CAPACITY: usize = 1000;
+
+/// The journal is moved to `events.jsonl.1` once it grows past this size
+const JOURNAL_SIZE: u64 = 1024 * 1024;
+
+/// Something that happened in the box, recorded as a JSON object with the
+/// event's name and time first
+#[derive(Clone, Debug)]
+pub struct Event {
+    name: String,
+    fields: Vec<(String, Field)>,
+}
+
+impl Event {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            fields: vec![],
+        }
+    }
+
+    pub fn with(mut self, key: &str, value: impl Into<Field>) -> Self {
+        self.fields.push((key.to_string(), value.into()));
+        self
+    }
+
+    /// As one line of tab-separated fields: the name, then the key, type
+    /// and value of each field
+    fn to_line(&self) -> String {
+        let mut columns = vec![escape(&self.name)];
+        for (key, value) in &self.fields {
+            let (kind, value) = match value {
+                Field::Text(text) => ("s", escape(text)),
+                Field::Number(number) => ("x", number.to_string()),
+                Field::Float(number) => ("d", number.to_string()),
+                Field::Bytes(bytes) => ("t", bytes.to_string()),
+                Field::Null => ("n", String::new()),
+            };
+            columns.extend([escape(key), kind.to_string(), value]);
+        }
+        columns.join("\t")
+    }
+
+    fn parse_line(line: &str) -> Option<Self> {
+        let mut columns = line.split('\t');
+        let mut event = Self::new(&unescape(columns.next()?));
+        while let Some(key) = columns.next() {
+            let value = match (columns.next()?, columns.next()?) {
+                ("s", text) => Field::Text(unescape(text)),
+                ("x", number) => Field::Number(number.parse().ok()?),
+                ("d", number) => Field::Float(number.parse().ok()?),
+                ("t", bytes) => Field::Bytes(bytes.parse().ok()?),
+                ("n", _) => Field::Null,
+                _ => return None,
+            };
+            event = event.with(&unescape(key), value);
+        }
+        Some(event)
+    }
+
+    fn to_json(&self, time: SystemTime) -> String {
+        let millis = time
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis())
+            .unwrap_or_default();
+        let mut members = vec![
+            format!("\"event\":{}", json_string(&self.name)),
+            format!("\"time\":{}", millis as f64 / 1000.0),
+        ];
+        for (key, value) in &self.fields {
+            members.push(format!("{}:{}", json_string(key), json_value(value)));
+        }
+        format!("{{{}}}", members.join(","))
+    }
+}
+
+/// Whether `name` is usable as an event name, like `session-exited`
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '-')
+}
+
+pub fn journal_path(config: &Config) -> PathBuf {
+    config.xdg_state_home().join("brief/events.jsonl")
+}
+
+/// The service's record of events: the latest ones in memory, and all of them
+/// appended to a journal in `XDG_STATE_HOME`. Once a bus connection is
+/// attached, each event is also broadcast as the `Event` signal.
+#[derive(Clone)]
+pub struct Journal {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    recent: VecDeque<String>,
+    path: PathBuf,
+    bus: Option<Connection>,
+}
+
+/// The journal of the service, when this process is the service
+static JOURNAL: OnceLock<Journal> = OnceLock::new();
+
+/// Events left for the reporter of the next `Session`, see `defer`
+static DEFERRED: Mutex<Vec<Event>> = Mutex::new(Vec::new());
+
+impl Journal {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                recent: VecDeque::new(),
+                path,
+                bus: None,
+            })),
+        }
+    }
+
+    /// Make this the journal that `emit` records to in this process
+    pub fn install(&self) {
+        let _ = JOURNAL.set(self.clone());
+    }
+
+    pub fn attach(&self, bus: Connection) {
+        self.inner.lock().unwrap().bus = Some(bus);
+    }
+
+    pub fn record(&self, event: &Event) {
+        let line = event.to_json(SystemTime::now());
+        let bus = {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.recent.len() == CAPACITY {
+                inner.recent.pop_front();
+            }
+            inner.recent.push_back(line.clone());
+            if let Err(err) = append(&inner.path, &line) {
+                eprintln!("Could not write '{}': {}", inner.path.display(), err);
+            }
+            inner.bus.clone()
+        };
+
+        if let Some(bus) = bus {
+            if let Err(err) =
+                bus.emit_signal(None::<&str>, dbus::PATH, dbus::NAME, "Event", &(line,))
+            {
+                eprintln!("Could not broadcast event: {}", err);
+            }
+        }
+    }
+
+    /// The latest events as JSON, oldest first
+    pub fn recent(&self) -> Vec<String> {
+        self.inner.lock().unwrap().recent.iter().cloned().collect()
+    }
+}
+
+fn append(path: &Path, line: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    if fs::metadata(path).map(|x| x.len()).unwrap_or_default() > JOURNAL_SIZE {
+        let mut rotated = path.as_os_str().to_owned();
+        rotated.push(".1");
+        fs::rename(path, rotated)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)
+}
+
+/// Record `event` in the service's journal, directly when called by the
+/// service and through its `Emit` method otherwise. Events are dropped when
+/// the service isn't running, which is not started for them. The bus
+/// connection leaves threads behind, so a process that joins the box later
+/// uses `defer` instead.
+pub fn emit(event: Event) {
+    match JOURNAL.get() {
+        Some(journal) => journal.record(&event),
+        None => {
+            let _ = send(&event);
+        }
+    }
+}
+
+/// Have `event` sent by the reporter of the `Session` we start next, which
+/// is forked before we join the box
+pub fn defer(event: Event) {
+    DEFERRED.lock().unwrap().push(event);
+}
+
+fn send(event: &Event) -> zbus::Result<()> {
+    let mut fields: HashMap<&str, Value> = HashMap::new();
+    for (key, value) in &event.fields {
+        let value = match value {
+            Field::Text(text) => Value::from(text.as_str()),
+            Field::Number(number) => Value::from(*number),
+            Field::Float(number) => Value::from(*number),
+            Field::Bytes(bytes) => Value::from(*bytes),
+            Field::Null => continue,
+        };
+        fields.insert(key, value);
+    }
+
+    let conn = Connection::session()?;
+    let proxy = Proxy::new(&conn, dbus::NAME, dbus::PATH, dbus::NAME)?;
+    proxy.call_with_flags::<_, _, ()>(
+        "Emit",
+        MethodFlags::NoAutoStart.into(),
+        &(event.name.as_str(), fields),
+    )?;
+    Ok(())
+}
+
+/// Where a process that isn't the service records events of a session
+#[derive(Clone)]
+pub enum Reporter {
+    /// The service's own journal, for sessions started by the service
+    Journal(Journal),
+    /// The pipe to the reporter of a `run` or `enter` session
+    Session(Arc<File>),
+}
+
+impl Reporter {
+    pub fn report(&self, event: Event) {
+        match self {
+            Self::Journal(journal) => journal.record(&event),
+            // One write, which a pipe doesn't interleave with others
+            Self::Session(pipe) => {
+                let _ = (&**pipe).write_all(format!("{}\n", event.to_line()).as_bytes());
+            }
+        }
+    }
+}
+
+/// Reports the start and end of a `run` or `enter` session. Joining the box
+/// leaves us without a way to the service's bus, so the reporting is done by
+/// a process forked beforehand, which stays outside. It learns of events in
+/// the session and finally the exit code through a pipe.
+pub struct Session {
+    pipe: OwnedFd,
+}
+
+impl Session {
+    /// Fork the reporter. Like `enterns`, this needs us to have no threads.
+    pub fn start(kind: &str, argv: &[String]) -> Option<Self> {
+        let (reader, writer) = pipe2(OFlag::O_CLOEXEC).ok()?;
+        match unsafe { fork() } {
+            Ok(ForkResult::Child) => {
+                drop(writer);
+                report(kind, argv, reader);
+                unsafe { libc::_exit(0) }
+            }
+            Ok(ForkResult::Parent { .. }) => Some(Self { pipe: writer }),
+            Err(err) => {
+                eprintln!("Could not fork: {}", err);
+                None
+            }
+        }
+    }
+
+    /// For events that happen once we're in the box, eg. from other threads
+    pub fn reporter(&self) -> Option<Reporter> {
+        let pipe = self.pipe.try_clone().ok()?;
+        Some(Reporter::Session(Arc::new(File::from(pipe))))
+    }
+
+    pub fn finish(self, code: i32) {
+        let _ = File::from(self.pipe).write_all(format!("{}\n", code).as_bytes());
+    }
+}
+
+fn report(kind: &str, argv: &[String], pipe: OwnedFd) {
+    // Out of the way of the terminal and its signals, so that we outlive a ^C
+    let _ = setsid();
+    if let Ok(null) = File::options().read(true).write(true).open("/dev/null") {
+        for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+            unsafe { libc::dup2(null.as_raw_fd(), fd) };
+        }
+    }
+
+    let started = Instant::now();
+    let pid = getppid().as_raw();
+    let command = argv.join(" ");
+    let _ = send(
+        &Event::new("session-started")
+            .with("session", kind)
+            .with("pid", pid)
+            .with("command", command.as_str()),
+    );
+    for event in DEFERRED.lock().unwrap().drain(..) {
+        let _ = send(&event);
+    }
+
+    // Events, one per line, until the exit code. That is missing when the
+    // session ended without telling us, eg. killed.
+    let mut code = None;
+    for line in BufReader::new(File::from(pipe))
+        .lines()
+        .map_while(Result::ok)
+    {
+        match line.parse::<i32>() {
+            Ok(number) => code = Some(number),
+            Err(_) => {
+                if let Some(event) = Event::parse_line(&line) {
+                    let _ = send(&event);
+                }
+            }
+        }
+    }
+    let _ = send(
+        &Event::new("session-exited")
+            .with("session", kind)
+            .with("pid", pid)
+            .with("command", command)
+            .with("exit_code", code)
+            .with("duration", Field::Float(started.elapsed().as_secs_f64())),
+    );
+}
+
+/// Record mounts appearing and disappearing in the box, from the service's
+/// mount table
+pub fn watch_mounts() {
+    thread::spawn(|| {
+        const PATH: &str = "/proc/self/mountinfo";
+        let Ok(file) = File::open(PATH) else {
+            eprintln!("Could not watch mounts: {} is missing", PATH);
+            return;
+        };
+        let read = || -> HashMap<u32, Mount> {
+            let text = fs::read_to_string(PATH).unwrap_or_default();
+            parse_mountinfo(&text)
+                .into_iter()
+                .map(|x| (x.id, x))
+                .collect()
+        };
+
+        let mut mounts = read();
+        loop {
+            // The mount table signals changes as an exceptional condition
+            let mut fds = libc::pollfd {
+                fd: file.as_raw_fd(),
+                events: libc::POLLPRI,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut fds, 1, -1) } < 0 {
+                if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                eprintln!("Could not watch mounts: {}", io::Error::last_os_error());
+                return;
+            }
+            let current = read();
+            for (id, mount) in &current {
+                if !mounts.contains_key(id) {
+                    emit(mount_event("mount-added", mount));
+                }
+            }
+            for (id, mount) in &mounts {
+                if !current.contains_key(id) {
+                    emit(mount_event("mount-removed", mount));
+                }
+            }
+            mounts = current;
+        }
+    });
+}
+
+fn mount_event(name: &str, mount: &Mount) -> Event {
+    Event::new(name)
+        .with("target", mount.target.as_str())
+        .with("source", mount.source.as_str())
+        .with("root", mount.root.as_str())
+        .with("fstype", mount.fstype.as_str())
+}
+
+/// Print the service's recent events as JSON lines, or the journal when it
+/// isn't running. With `follow`, keep printing new events as they happen.
+pub fn show(config: &Config, follow: bool) -> ExitCode {
+    let conn = match Connection::session() {
+        Ok(conn) => conn,
+        Err(err) => {
+            eprintln!("Could not connect to the session bus: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let proxy = match Proxy::new(&conn, dbus::NAME, dbus::PATH, dbus::NAME) {
+        Ok(proxy) => proxy,
+        Err(err) => {
+            eprintln!("Could not connect to nixbox: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    // Subscribe first, so that nothing between the two is missed
+    let signals = match follow {
+        true => match proxy.receive_signal("Event") {
+            Ok(signals) => Some(signals),
+            Err(err) => {
+                eprintln!("Could not subscribe to events: {}", err);
+                return ExitCode::FAILURE;
+            }
+        },
+        false => None,
+    };
+
+    let recent = proxy
+        .call_with_flags::<_, _, Vec<String>>("ListEvents", MethodFlags::NoAutoStart.into(), &())
+        .ok()
+        .flatten();
+    let lines = match recent {
+        Some(lines) => lines,
+        None => read_journal(&journal_path(config)),
+    };
+    let mut stdout = io::stdout();
+    for line in lines {
+        if writeln!(stdout, "{}", line).is_err() {
+            return ExitCode::SUCCESS;
+        }
+    }
+
+    for message in signals.into_iter().flatten() {
+        let Ok(line) = message.body().deserialize::<String>() else {
+            continue;
+        };
+        if writeln!(stdout, "{}", line)
+            .and_then(|_| stdout.flush())
+            .is_err()
+        {
+            break;
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// The last `CAPACITY` events of the journal
+fn read_journal(path: &Path) -> Vec<String> {
+    let Ok(file) = File::open(path) else {
+        return vec![];
+    };
+    let mut lines = VecDeque::new();
+    for line in BufReader::new(file).lines().map_while(Result::ok) {
+        if lines.len() == CAPACITY {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+    lines.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn it_encodes_events() {
+        let event = Event::new("session-exited")
+            .with("pid", 12)
+            .with("command", "echo \"hi\"")
+            .with("exit_code", None::<i32>);
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+
+        assert_eq!(
+            event.to_json(time),
+            r#"{"event":"session-exited","time":1700000000.25,"pid":12,"command":"echo \"hi\"","exit_code":null}"#
+        );
+    }
+
+    #[test]
+    fn it_passes_events_as_lines() {
+        let event = Event::new("syscall-blocked")
+            .with("pid", 12)
+            .with("command", "a\tb")
+            .with("duration", Field::Float(0.5))
+            .with("exit_code", None::<i32>);
+        let time = UNIX_EPOCH;
+
+        let parsed = Event::parse_line(&event.to_line()).unwrap();
+        assert_eq!(parsed.to_json(time), event.to_json(time));
+        assert!(Event::parse_line("name\tkey\ts").is_none());
+    }
+}
@@ -0,0 +1,262 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use crate::app;
+use crate::config::Config;
+use crate::events::{self, Event};
+use crate::output::{Format, Listing, Output};
+
+const SHIM_MARKER: &str = "# brief-export-bin: ";
+
+pub fn default_bindir(config: &Config) -> PathBuf {
+    if let Some(dir) = config.settings.get("Export", "BinDir") {
+        return PathBuf::from(dir);
+    }
+    PathBuf::from(env::var_os("HOME").expect("Environment variable HOME not set"))
+        .join(".local/bin")
+}
+
+pub fn bin(config: &Config, bindir: &Path, names: &[String]) -> ExitCode {
+    fs::create_dir_all(bindir)
+        .unwrap_or_else(|err| panic!("Could not create '{}': {}", bindir.display(), err));
+
+    let mut code = ExitCode::SUCCESS;
+    for name in names {
+        if name.contains('/') {
+            eprintln!("Cannot export '{}': not a command name", name);
+            code = ExitCode::FAILURE;
+            continue;
+        }
+        if find_target(config, name).is_none() {
+            eprintln!("Cannot export '{}': not found in the box profile", name);
+            code = ExitCode::FAILURE;
+            continue;
+        }
+
+        let path = bindir.join(name);
+        if path.symlink_metadata().is_ok() && Shim::parse_file(&path).is_none() {
+            eprintln!(
+                "Cannot export '{}': '{}' exists and was not created by brief",
+                name,
+                path.display()
+            );
+            code = ExitCode::FAILURE;
+            continue;
+        }
+
+        write_shim(config, &path, name)
+            .unwrap_or_else(|err| panic!("Could not write '{}': {}", path.display(), err));
+        println!("Exported {} to {}", name, path.display());
+    }
+    code
+}
+
+pub fn list(config: &Config, bindir: &Path, output: &Output) -> ExitCode {
+    let shims = Shim::find_all(bindir);
+    if shims.is_empty() && output.format == Format::Table {
+        println!("No commands exported to {}", bindir.display());
+        return ExitCode::SUCCESS;
+    }
+
+    let mut listing = Listing::new(&[("name", "NAME"), ("status", "STATUS"), ("path", "PATH")]);
+
+    for shim in shims {
+        let status = match find_target(config, &shim.name) {
+            Some(_) => "ok",
+            None => "missing",
+        };
+        listing.add_row(vec![
+            shim.name.into(),
+            status.into(),
+            shim.path.display().to_string().into(),
+        ]);
+    }
+
+    listing.print(output)
+}
+
+pub fn remove(bindir: &Path, names: &[String]) -> ExitCode {
+    let shims = Shim::find_all(bindir);
+
+    let mut code = ExitCode::SUCCESS;
+    for name in names {
+        let Some(shim) = shims.iter().find(|shim| &shim.name == name) else {
+            eprintln!("'{}' is not exported to {}", name, bindir.display());
+            code = ExitCode::FAILURE;
+            continue;
+        };
+        fs::remove_file(&shim.path)
+            .unwrap_or_else(|err| panic!("Could not remove '{}': {}", shim.path.display(), err));
+        println!("Removed {}", shim.path.display());
+    }
+    code
+}
+
+pub fn check(config: &Config, bindir: &Path) -> ExitCode {
+    let mut code = ExitCode::SUCCESS;
+    for shim in Shim::find_all(bindir) {
+        if find_target(config, &shim.name).is_none() {
+            println!(
+                "{}: '{}' no longer exists in the box profile",
+                shim.path.display(),
+                shim.name
+            );
+            code = ExitCode::FAILURE;
+        }
+    }
+    code
+}
+
+/// Bring exported applications and commands up to date with the box's
+/// profile. Exports of things that were uninstalled are removed, and
+/// everything listed under `[Export]` in `brief.conf` is exported.
+pub fn sync(config: &Config, bindir: &Path) -> ExitCode {
+    let apps = app::find_all(config).unwrap_or_default();
+    let exported = app::Exported::find_all();
+    let mut changed = 0;
+
+    for entry in &exported {
+        match apps.iter().find(|app| app.id == entry.id) {
+            Some(app) => {
+                if let Err(err) = app::export(config, app) {
+                    eprintln!("Could not update '{}': {}", entry.path.display(), err);
+                }
+            }
+            None => {
+                if entry.remove().is_ok() {
+                    println!("Removed {}", entry.path.display());
+                    changed += 1;
+                }
+            }
+        }
+    }
+
+    for id in config.settings.get_list("Export", "Apps") {
+        if exported.iter().any(|entry| entry.id == id) {
+            continue;
+        }
+        let Some(app) = apps.iter().find(|app| app.id == id) else {
+            continue;
+        };
+        match app::export(config, app) {
+            Ok(path) => {
+                println!("Exported {} to {}", id, path.display());
+                changed += 1;
+            }
+            Err(err) => eprintln!("Could not export application '{}': {}", id, err),
+        }
+    }
+    app::update_integration(config);
+
+    let shims = Shim::find_all(bindir);
+    for shim in &shims {
+        if find_target(config, &shim.name).is_none() && fs::remove_file(&shim.path).is_ok() {
+            println!("Removed {}", shim.path.display());
+            changed += 1;
+        }
+    }
+
+    for name in config.settings.get_list("Export", "Bins") {
+        if shims.iter().any(|shim| shim.name == name) || find_target(config, &name).is_none() {
+            continue;
+        }
+        let path = bindir.join(&name);
+        if path.symlink_metadata().is_ok() {
+            continue;
+        }
+        match fs::create_dir_all(bindir).and_then(|_| write_shim(config, &path, &name)) {
+            Ok(_) => {
+                println!("Exported {} to {}", name, path.display());
+                changed += 1;
+            }
+            Err(err) => eprintln!("Could not export '{}': {}", name, err),
+        }
+    }
+
+    events::emit(Event::new("exports-synced").with("changed", changed));
+    ExitCode::SUCCESS
+}
+
+/// Find the store path of an executable in the box's profile, in the same
+/// order as the box's PATH.
+pub fn find_target(config: &Config, name: &str) -> Option<PathBuf> {
+    let profiles = [
+        config.nix_profile.as_ref().map(|x| x.join("bin")),
+        config.current_system.as_ref().map(|x| x.join("sw/bin")),
+    ];
+
+    profiles
+        .iter()
+        .flatten()
+        .filter_map(|dir| config.resolve_symlink(dir.join(name)).ok())
+        .find(|path| path.is_file())
+}
+
+fn write_shim(config: &Config, path: &Path, name: &str) -> io::Result<()> {
+    let executable = config.nixbox_executable();
+
+    let mut file = File::create(path)?;
+    writeln!(file, "#!/bin/sh")?;
+    writeln!(file, "# Exported from nixbox by brief. Do not edit.")?;
+    writeln!(file, "{}{}", SHIM_MARKER, name)?;
+    writeln!(
+        file,
+        "exec {} run --shim -- {} \"$@\"",
+        shell_quote(&executable.to_string_lossy()),
+        shell_quote(name)
+    )?;
+
+    let mut perms = file.metadata()?.permissions();
+    perms.set_mode(0o755);
+    fs::set_permissions(path, perms)
+}
+
+pub fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+pub struct Shim {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl Shim {
+    pub fn find_all(bindir: &Path) -> Vec<Self> {
+        let Ok(dir) = fs::read_dir(bindir) else {
+            return vec![];
+        };
+
+        let mut shims: Vec<Self> = dir
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| Self::parse_file(entry.path()))
+            .collect();
+        shims.sort_by(|a, b| a.name.cmp(&b.name));
+        shims
+    }
+
+    fn parse_file(path: impl AsRef<Path>) -> Option<Self> {
+        let path = path.as_ref();
+        if !path.is_file() {
+            return None;
+        }
+
+        let file = File::open(path).ok()?;
+        let reader = BufReader::new(file);
+
+        // The marker is always within the first few lines of the shim
+        for line in reader.lines().take(4) {
+            let line = line.ok()?;
+            if let Some(name) = line.strip_prefix(SHIM_MARKER) {
+                return Some(Self {
+                    name: name.trim().to_string(),
+                    path: path.to_owned(),
+                });
+            }
+        }
+        None
+    }
+}
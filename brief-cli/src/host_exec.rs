@@ -0,0 +1,332 @@
+use std::convert::{TryFrom, TryInto};
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{self, IoSlice, IoSliceMut, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, ExitCode, Stdio};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+
+use nix::sys::prctl::set_pdeathsig;
+use nix::sys::signal::{kill, sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::sys::socket::sockopt::PeerCredentials;
+use nix::sys::socket::{
+    getsockopt, recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags,
+};
+use nix::unistd::{fork, setsid, ForkResult, Pid};
+
+use crate::command::exit_code;
+use crate::init::xdg_runtime_dir;
+
+/// Signals that `host-exec` passes on to the command on the host
+const FORWARD_SIGNALS: &[Signal] = &[
+    Signal::SIGHUP,
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTERM,
+    Signal::SIGUSR1,
+    Signal::SIGUSR2,
+    Signal::SIGWINCH,
+];
+
+/// Socket of the host-side helper, under `/run` which is shared with the box
+pub fn socket_path() -> PathBuf {
+    xdg_runtime_dir().join("nixbox/host.sock")
+}
+
+/// A command to run on the host
+#[derive(Debug, PartialEq)]
+struct Request {
+    cwd: PathBuf,
+    argv: Vec<OsString>,
+    env: Vec<OsString>,
+}
+
+impl Request {
+    /// NUL-terminated fields: the working directory, the number of arguments,
+    /// the arguments and finally `KEY=VALUE` environment variables
+    fn encode(&self) -> Vec<u8> {
+        let argc = OsString::from(self.argv.len().to_string());
+        let mut fields = vec![self.cwd.as_os_str(), argc.as_os_str()];
+        fields.extend(self.argv.iter().map(OsString::as_os_str));
+        fields.extend(self.env.iter().map(OsString::as_os_str));
+
+        let mut bytes = vec![];
+        for field in fields {
+            bytes.extend_from_slice(field.as_bytes());
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut fields = bytes
+            .strip_suffix(b"\0")?
+            .split(|byte| *byte == 0)
+            .map(|field| OsString::from_vec(field.to_vec()));
+        let cwd = PathBuf::from(fields.next()?);
+        let argc: usize = fields.next()?.to_str()?.parse().ok()?;
+        let argv: Vec<OsString> = fields.by_ref().take(argc).collect();
+        if argv.len() != argc || argc == 0 {
+            return None;
+        }
+        Some(Self {
+            cwd,
+            argv,
+            env: fields.collect(),
+        })
+    }
+}
+
+/// Fork a helper that stays outside the box's namespaces and runs commands
+/// for `host-exec` clients. Must be called before the box is set up. The
+/// helper exits together with the calling process.
+pub fn spawn_helper() {
+    let path = socket_path();
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)
+        .unwrap_or_else(|err| panic!("Could not bind '{}': {}", path.display(), err));
+
+    match unsafe { fork() } {
+        Ok(ForkResult::Parent { .. }) => (),
+        Ok(ForkResult::Child) => {
+            set_pdeathsig(Signal::SIGTERM)
+                .unwrap_or_else(|err| eprintln!("Could not set parent death signal: {}", err));
+            for client in listener.incoming() {
+                let Ok(client) = client else {
+                    continue;
+                };
+                thread::spawn(move || {
+                    if let Err(err) = serve(client) {
+                        eprintln!("host-exec: {}", err);
+                    }
+                });
+            }
+            process::exit(0);
+        }
+        Err(err) => panic!("fork failed: {}", err),
+    }
+}
+
+fn serve(mut client: UnixStream) -> io::Result<()> {
+    let (request, [stdin, stdout, stderr]) = receive(&mut client)?;
+
+    // Commands on the host would get around the filters of the session
+    let pid = getsockopt(&client, PeerCredentials)?.pid();
+    if is_sandboxed(&fs::read_to_string(format!("/proc/{}/status", pid))?) {
+        let _ = File::from(stderr).write_all(b"host-exec: not allowed in a sandboxed session\n");
+        return client.write_all(&126i32.to_le_bytes());
+    }
+
+    let (program, args) = request.argv.split_first().expect("argv is not empty");
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .stdin(Stdio::from(stdin))
+        .stdout(Stdio::from(stdout))
+        .stderr(Stdio::from(stderr));
+    if request.cwd.is_dir() {
+        command.current_dir(&request.cwd);
+    }
+    for var in &request.env {
+        let var = var.as_bytes();
+        if let Some(index) = var.iter().position(|c| *c == b'=') {
+            command.env(
+                OsStr::from_bytes(&var[..index]),
+                OsStr::from_bytes(&var[index + 1..]),
+            );
+        }
+    }
+
+    // Detach from the helper's session. The command gets no controlling
+    // terminal, so reading from a passed-in tty never stops it.
+    unsafe {
+        command.pre_exec(|| setsid().map(|_| ()).map_err(io::Error::from));
+    }
+
+    let code = match command.spawn() {
+        Ok(mut child) => {
+            let pid = Pid::from_raw(child.id() as i32);
+            let mut signals = client.try_clone()?;
+            thread::spawn(move || {
+                let mut buf = [0u8; 4];
+                while signals.read_exact(&mut buf).is_ok() {
+                    if let Ok(signal) = Signal::try_from(i32::from_le_bytes(buf)) {
+                        let _ = kill(pid, signal);
+                    }
+                }
+            });
+            exit_code(child.wait()?)
+        }
+        Err(err) => {
+            eprintln!(
+                "host-exec: failed to execute {}: {}",
+                program.to_string_lossy(),
+                err
+            );
+            127
+        }
+    };
+    client.write_all(&code.to_le_bytes())
+}
+
+/// Whether a process runs under a seccomp filter or can't gain privileges,
+/// as `--seccomp` and `--fs-allow` sessions, going by its `/proc/PID/status`
+pub fn is_sandboxed(status: &str) -> bool {
+    status.lines().any(|line| match line.split_once(':') {
+        Some(("NoNewPrivs", value)) => value.trim() == "1",
+        Some(("Seccomp", value)) => value.trim() == "2",
+        _ => false,
+    })
+}
+
+/// Read a request and the client's stdin, stdout and stderr. The length of
+/// the request is sent together with the file descriptors, then the request.
+fn receive(client: &mut UnixStream) -> io::Result<(Request, [OwnedFd; 3])> {
+    let mut len = [0u8; 4];
+    let mut fds = vec![];
+    let received = {
+        let mut iov = [IoSliceMut::new(&mut len)];
+        let mut cmsg = nix::cmsg_space!([RawFd; 3]);
+        let msg = recvmsg::<()>(
+            client.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        )?;
+        for cmsg in msg.cmsgs()? {
+            if let ControlMessageOwned::ScmRights(raw) = cmsg {
+                fds.extend(
+                    raw.into_iter()
+                        .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }),
+                );
+            }
+        }
+        msg.bytes
+    };
+    if received == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    client.read_exact(&mut len[received..])?;
+
+    let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+    client.read_exact(&mut bytes)?;
+    let request = Request::decode(&bytes)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed request"))?;
+    let fds: [OwnedFd; 3] = fds
+        .try_into()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "expected 3 file descriptors"))?;
+    Ok((request, fds))
+}
+
+/// Socket to the helper while a command runs, for the signal handler
+static SOCKET: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn forward_signal(signal: libc::c_int) {
+    let bytes = signal.to_le_bytes();
+    unsafe {
+        libc::write(
+            SOCKET.load(Ordering::SeqCst),
+            bytes.as_ptr() as *const libc::c_void,
+            bytes.len(),
+        );
+    }
+}
+
+/// Run `argv` on the host with this process's stdio, and exit with its status
+pub fn host_exec(argv: &[String], envs: &[String], directory: Option<&Path>) -> ExitCode {
+    let path = socket_path();
+    let mut socket = match UnixStream::connect(&path) {
+        Ok(socket) => socket,
+        Err(err) => {
+            eprintln!(
+                "Could not connect to the host at '{}': {}",
+                path.display(),
+                err
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let cwd = match directory {
+        Some(dir) => dir.to_owned(),
+        None => env::current_dir().unwrap_or_else(|_| PathBuf::from("/")),
+    };
+    let request = Request {
+        cwd,
+        argv: argv.iter().map(OsString::from).collect(),
+        env: envs.iter().map(OsString::from).collect(),
+    };
+    let bytes = request.encode();
+    let len = (bytes.len() as u32).to_le_bytes();
+
+    let stdio = [0, 1, 2];
+    sendmsg::<()>(
+        socket.as_raw_fd(),
+        &[IoSlice::new(&len)],
+        &[ControlMessage::ScmRights(&stdio)],
+        MsgFlags::empty(),
+        None,
+    )
+    .unwrap_or_else(|err| panic!("Could not send request to the host: {}", err));
+    socket
+        .write_all(&bytes)
+        .unwrap_or_else(|err| panic!("Could not send request to the host: {}", err));
+
+    SOCKET.store(socket.as_raw_fd(), Ordering::SeqCst);
+    let action = SigAction::new(
+        SigHandler::Handler(forward_signal),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for signal in FORWARD_SIGNALS {
+        unsafe { sigaction(*signal, &action) }
+            .unwrap_or_else(|err| panic!("Could not handle {}: {}", signal, err));
+    }
+
+    let mut code = [0u8; 4];
+    match socket.read_exact(&mut code) {
+        Ok(()) => ExitCode::from(i32::from_le_bytes(code) as u8),
+        Err(err) => {
+            eprintln!("Lost connection to the host: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_roundtrips_requests() {
+        let request = Request {
+            cwd: PathBuf::from("/home/user"),
+            argv: vec!["flatpak".into(), "list".into(), "".into()],
+            env: vec!["A=B".into(), "C=".into()],
+        };
+
+        assert_eq!(Request::decode(&request.encode()), Some(request));
+    }
+
+    #[test]
+    fn it_recognises_sandboxed_processes() {
+        let status = |x: &str| format!("Name:\tsh\n{}\nSeccomp_filters:\t0\n", x);
+        assert!(!is_sandboxed(&status("NoNewPrivs:\t0\nSeccomp:\t0")));
+        assert!(is_sandboxed(&status("NoNewPrivs:\t1\nSeccomp:\t0")));
+        assert!(is_sandboxed(&status("NoNewPrivs:\t0\nSeccomp:\t2")));
+    }
+
+    #[test]
+    fn it_rejects_malformed_requests() {
+        assert_eq!(Request::decode(b""), None);
+        assert_eq!(Request::decode(b"/\x000\x00"), None);
+        assert_eq!(Request::decode(b"/\x002\x00a\x00"), None);
+        assert_eq!(Request::decode(b"/\x00x\x00a\x00"), None);
+    }
+}
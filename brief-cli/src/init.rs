@@ -6,12 +6,25 @@ use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::symlink;
 use std::path::{Path, PathBuf};
 use std::process;
-
-use nix::unistd::{sethostname, unlink};
-
-use crate::command::run;
-use crate::config::Config;
+use std::thread;
+use std::time::Duration;
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::{sethostname, unlink, Pid};
+
+use crate::autostart;
+use crate::command::{command, run_command};
+use crate::config::{Config, BOX_NAME};
+use crate::dbus;
+use crate::dbus_proxy::{self, Policy};
+use crate::events::{self, Event, Journal};
+use crate::host_exec;
+use crate::limits::Limits;
+use crate::open;
 use crate::setup::setup;
+use crate::systemd;
+use crate::units::Supervisor;
+use crate::watch;
 
 const LOGIN_SCRIPT: &str = r#"
 echo $$ > $1
@@ -34,8 +47,13 @@ impl Service {
         Some(Service { pid, root, env })
     }
 
+    /// Terminate the box's login shell, which makes the service exit
+    pub fn stop(&self) -> nix::Result<()> {
+        kill(Pid::from_raw(self.pid), Signal::SIGTERM)
+    }
+
     pub fn init() -> Option<Self> {
-        let config = Config::new(true).unwrap();
+        let mut config = Config::new(true).unwrap();
 
         let rundir = xdg_runtime_dir().join("nixbox");
         if !rundir.is_dir() {
@@ -45,16 +63,73 @@ impl Service {
         let pidfile = rundir.join("server.pid");
         let envfile = rundir.join("environ");
         // write_pidfile(pidfile).expect("Could not create pidfile");
+        // Left over from an earlier box, they would make us look ready early
+        let _ = fs::remove_file(&pidfile);
+        let _ = fs::remove_file(&envfile);
 
         force_symlink(&config.chroot_dir, rundir.join("chroot"))
             .unwrap_or_else(|err| panic!("could not chroot symlink: {}", err));
 
+        // The helper has to be forked while we are still outside the box
+        if config.settings.get_bool("Service", "HostExec") == Some(true) {
+            host_exec::spawn_helper();
+        }
+
         setup(&config);
-        sethostname("nixbox").unwrap_or_else(|err| eprintln!("Could not set hostname: {}", err));
+        sethostname(BOX_NAME).unwrap_or_else(|err| eprintln!("Could not set hostname: {}", err));
+        // The limits go on what we start, not on us: hitting one must not
+        // take down the service and with it the box
+        let limits = Limits::from_settings(&config.settings).unwrap_or_else(|err| {
+            eprintln!("Invalid box settings: {}", err);
+            Limits::default()
+        });
+
+        let events = Journal::new(events::journal_path(&config));
+        events.install();
+        events::emit(Event::new("service-started").with("pid", process::id()));
+        events::watch_mounts();
+
+        if config.settings.get_bool("Service", "WatchExports") == Some(true) {
+            watch::spawn(config.clone());
+        }
+
+        let host_bus = match config.settings.get_bool("DBusProxy", "Enable") {
+            Some(true) => start_dbus_proxy(&mut config, &rundir.join("bus")),
+            _ => None,
+        };
+        if config.settings.get_bool("Open", "Shim") != Some(false) {
+            open::install_shim(&mut config, &rundir.join("bin"))
+                .unwrap_or_else(|err| eprintln!("Could not install xdg-open shim: {}", err));
+        }
+
+        // Keep the connection, and with it our name on the bus, for as long
+        // as the service runs
+        let units = Supervisor::new(config.clone());
+        let _bus = dbus::serve(config.clone(), units.clone(), events)
+            .map_err(|err| eprintln!("Could not register on the session bus: {}", err))
+            .ok();
+        // Only now, as we needed the host's bus ourselves
+        if let Some(Err(err)) = host_bus.map(|x| dbus_proxy::hide(&x)) {
+            eprintln!("Could not hide the session bus from the box: {}", err);
+        }
+        if config.settings.get_bool("Service", "Autostart") == Some(true) {
+            autostart::start(&config);
+        }
+        if config.settings.get_bool("Service", "Units") != Some(false) {
+            units.start_enabled();
+        }
 
         println!("nixbox initialised");
+        // The box is ready for clients once the login shell wrote its files
+        thread::spawn(|| {
+            while Service::from_existing().is_none() {
+                thread::sleep(Duration::from_millis(50));
+            }
+            systemd::notify("READY=1\nSTATUS=nixbox initialised");
+            events::emit(Event::new("service-ready"));
+        });
         let envs = vec![("A", "B")];
-        run(
+        let mut shell = command(
             &config,
             "/run/current-system/sw/bin/bash",
             [
@@ -67,10 +142,36 @@ impl Service {
             ],
             envs,
         );
+        limits.apply_to(&mut shell);
+        let code = run_command(&mut shell);
+        events::emit(Event::new("service-stopping").with("exit_code", code));
         None
     }
 }
 
+/// Serve a filtered view of the session bus on `path` and point the box's
+/// processes at it instead of the host's bus, whose address is returned
+fn start_dbus_proxy(config: &mut Config, path: &Path) -> Option<String> {
+    let Some(address) = env::var("DBUS_SESSION_BUS_ADDRESS").ok() else {
+        eprintln!("Could not start D-Bus proxy: DBUS_SESSION_BUS_ADDRESS is not set");
+        return None;
+    };
+    match dbus_proxy::spawn(&address, path, Policy::from_settings(&config.settings)) {
+        Ok(()) => {
+            let mut proxy = OsString::from("unix:path=");
+            proxy.push(path);
+            config
+                .env
+                .insert(OsString::from("DBUS_SESSION_BUS_ADDRESS"), proxy);
+            Some(address)
+        }
+        Err(err) => {
+            eprintln!("Could not start D-Bus proxy: {}", err);
+            None
+        }
+    }
+}
+
 fn get_pid() -> Option<i32> {
     let file = File::open(xdg_runtime_dir().join("nixbox/server.pid")).ok()?;
     let mut reader = BufReader::new(file);
@@ -99,7 +200,7 @@ fn get_env() -> Option<Vec<(OsString, OsString)>> {
     Some(env)
 }
 
-fn xdg_runtime_dir() -> PathBuf {
+pub fn xdg_runtime_dir() -> PathBuf {
     PathBuf::from(&env::var_os("XDG_RUNTIME_DIR").expect("XDG_RUNTIME_DIR is not set"))
 }
 
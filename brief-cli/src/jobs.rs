@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::Value;
+
+use crate::config::Config;
+use crate::dbus;
+use crate::output::{Format, Listing, Output};
+use crate::util::KeyFile;
+
+const JOB: &str = "Job";
+
+/// A command started detached with `run --detach`. Jobs are recorded as key
+/// files next to their logs in `XDG_STATE_HOME/brief/jobs`.
+pub struct Job {
+    pub name: String,
+    pub pid: u32,
+    /// Start time of the process, see `start_time`
+    start_time: Option<u64>,
+    pub command: String,
+    /// Seconds since the epoch
+    pub started: u64,
+    pub exit_code: Option<i32>,
+    path: PathBuf,
+}
+
+pub fn jobs_dir(config: &Config) -> PathBuf {
+    config.xdg_state_home().join("brief/jobs")
+}
+
+/// Job names end up in file names, so keep them simple
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+impl Job {
+    pub fn new(config: &Config, name: &str, pid: u32, argv: &[String]) -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or_default();
+        Self {
+            name: name.to_string(),
+            pid,
+            start_time: start_time(pid),
+            command: argv.join(" "),
+            started,
+            exit_code: None,
+            path: jobs_dir(config).join(format!("{}.job", name)),
+        }
+    }
+
+    pub fn load(config: &Config, name: &str) -> Option<Self> {
+        Self::parse_file(jobs_dir(config).join(format!("{}.job", name)))
+    }
+
+    /// All jobs, ordered by name
+    pub fn find_all(config: &Config) -> Vec<Self> {
+        let Ok(dir) = fs::read_dir(jobs_dir(config)) else {
+            return vec![];
+        };
+        let mut jobs: Vec<Self> = dir
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .path()
+                    .extension()
+                    .map(|x| x == "job")
+                    .unwrap_or(false)
+            })
+            .filter_map(|entry| Self::parse_file(entry.path()))
+            .collect();
+        jobs.sort_by(|a, b| a.name.cmp(&b.name));
+        jobs
+    }
+
+    fn parse_file(path: PathBuf) -> Option<Self> {
+        let keyfile = KeyFile::parse_file(&path).ok()?;
+        let group = keyfile.group(JOB)?;
+        Some(Self {
+            name: group.get("Name")?.to_string(),
+            pid: group.get("Pid")?.parse().ok()?,
+            start_time: group.get("StartTime").and_then(|x| x.parse().ok()),
+            command: group.get("Command").unwrap_or_default().to_string(),
+            started: group
+                .get("Started")
+                .and_then(|x| x.parse().ok())
+                .unwrap_or_default(),
+            exit_code: group.get("ExitCode").and_then(|x| x.parse().ok()),
+            path,
+        })
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut text = format!(
+            "[{}]\nName={}\nPid={}\nCommand={}\nStarted={}\n",
+            JOB,
+            self.name,
+            self.pid,
+            self.command.replace('\n', " "),
+            self.started
+        );
+        if let Some(start_time) = self.start_time {
+            text.push_str(&format!("StartTime={}\n", start_time));
+        }
+        if let Some(code) = self.exit_code {
+            text.push_str(&format!("ExitCode={}\n", code));
+        }
+        File::create(&self.path)?.write_all(text.as_bytes())
+    }
+
+    pub fn log_path(&self) -> PathBuf {
+        self.path.with_extension("log")
+    }
+
+    /// Whether the job's process is still alive. A job whose exit wasn't
+    /// recorded, eg. because the service was stopped, counts as running only
+    /// while its PID belongs to the same process, and not one that got the
+    /// PID after it.
+    pub fn is_running(&self) -> bool {
+        self.exit_code.is_none()
+            && self.start_time.is_some()
+            && start_time(self.pid) == self.start_time
+    }
+
+    pub fn status(&self) -> String {
+        match self.exit_code {
+            Some(code) => format!("exited ({})", code),
+            None if self.is_running() => String::from("running"),
+            None => String::from("lost"),
+        }
+    }
+}
+
+/// Create the log file of job `name`, replacing the output of an earlier job
+/// of the same name
+/// When `pid` started, in clock ticks since boot. With the PID it tells a
+/// process apart from a later one that was given the same PID.
+fn start_time(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(Path::new("/proc").join(pid.to_string()).join("stat")).ok()?;
+    // Fields are counted from the state, which follows the command name
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+pub fn create_log(config: &Config, name: &str) -> io::Result<File> {
+    let dir = jobs_dir(config);
+    fs::create_dir_all(&dir)?;
+    File::create(dir.join(format!("{}.log", name)))
+}
+
+/// A name for a new job running `program` that isn't used by a running job
+pub fn unused_name(config: &Config, program: &str) -> String {
+    let base: String = Path::new(program)
+        .file_name()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if is_valid_name(&c.to_string()) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let base = if is_valid_name(&base) {
+        base
+    } else {
+        String::from("job")
+    };
+
+    let taken = |name: &str| {
+        Job::load(config, name)
+            .map(|job| job.is_running())
+            .unwrap_or(false)
+    };
+    let mut name = base.clone();
+    let mut index = 1;
+    while taken(&name) {
+        index += 1;
+        name = format!("{}-{}", base, index);
+    }
+    name
+}
+
+/// Ask the service to start `argv` as the job `name`
+pub fn start(
+    name: &str,
+    argv: &[String],
+    env: &HashMap<String, String>,
+    cwd: &Path,
+    options: HashMap<&str, Value>,
+) -> ExitCode {
+    let result = Connection::session()
+        .and_then(|conn| Proxy::new(&conn, dbus::NAME, dbus::PATH, dbus::NAME))
+        .and_then(|proxy| {
+            proxy.call::<_, _, u32>(
+                "StartJob",
+                &(name, argv, env, cwd.to_string_lossy().as_ref(), options),
+            )
+        });
+    match result {
+        Ok(pid) => {
+            println!("Started {} (PID: {})", name, pid);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("Could not start job '{}': {}", name, err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+pub fn list(config: &Config, output: &Output) -> ExitCode {
+    let jobs = Job::find_all(config);
+    if jobs.is_empty() && output.format == Format::Table {
+        println!("No jobs");
+        return ExitCode::SUCCESS;
+    }
+
+    let mut listing = Listing::new(&[
+        ("name", "NAME"),
+        ("pid", "PID"),
+        ("status", "STATUS"),
+        ("exit_code", ""),
+        ("started", ""),
+        ("age", "STARTED"),
+        ("command", "COMMAND"),
+    ]);
+    listing.truncate(&["command"]);
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default();
+    for job in jobs {
+        listing.add_row(vec![
+            job.name.clone().into(),
+            job.pid.into(),
+            job.status().into(),
+            job.exit_code.into(),
+            (job.started as i64).into(),
+            format!("{} ago", format_age(now.saturating_sub(job.started))).into(),
+            job.command.clone().into(),
+        ]);
+    }
+    listing.print(output)
+}
+
+pub fn format_age(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+/// Print the output of a job. With `follow`, keep printing new output until
+/// the job exits.
+pub fn logs(config: &Config, name: &str, follow: bool) -> ExitCode {
+    let Some(job) = Job::load(config, name) else {
+        eprintln!("No job named '{}'", name);
+        return ExitCode::FAILURE;
+    };
+    let path = job.log_path();
+    let mut file = match File::open(&path) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Could not open '{}': {}", path.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut stdout = io::stdout();
+    loop {
+        // Check before reading, so that output written just before the job
+        // exited is not missed
+        let running = follow && Job::load(config, name).map(|x| x.is_running()) == Some(true);
+        if let Err(err) = io::copy(&mut file, &mut stdout).and_then(|_| stdout.flush()) {
+            eprintln!("Could not read '{}': {}", path.display(), err);
+            return ExitCode::FAILURE;
+        }
+        if !running {
+            return ExitCode::SUCCESS;
+        }
+
+        sleep(Duration::from_millis(250));
+        // Start over if the log was replaced by a new job of the same name
+        let position = file.stream_position().unwrap_or_default();
+        if fs::metadata(&path)
+            .map(|x| x.len() < position)
+            .unwrap_or(false)
+        {
+            if let Ok(reopened) = File::open(&path) {
+                file = reopened;
+            }
+        } else {
+            let _ = file.seek(SeekFrom::Start(position));
+        }
+    }
+}
+
+/// Send `signal` to the process group of a running job
+pub fn kill(config: &Config, name: &str, signal: &str) -> ExitCode {
+    let Some(signal) = parse_signal(signal) else {
+        eprintln!("Unknown signal '{}'", signal);
+        return ExitCode::FAILURE;
+    };
+    let Some(job) = Job::load(config, name) else {
+        eprintln!("No job named '{}'", name);
+        return ExitCode::FAILURE;
+    };
+    if !job.is_running() {
+        eprintln!("Job '{}' is not running", name);
+        return ExitCode::FAILURE;
+    }
+
+    match killpg(Pid::from_raw(job.pid as i32), signal) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Could not signal job '{}': {}", name, err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Parse a signal given as a number, or a name with or without `SIG`
+pub fn parse_signal(text: &str) -> Option<Signal> {
+    if let Ok(number) = text.parse::<i32>() {
+        return Signal::try_from(number).ok();
+    }
+    let name = text.to_ascii_uppercase();
+    match name.starts_with("SIG") {
+        true => Signal::from_str(&name).ok(),
+        false => Signal::from_str(&format!("SIG{}", name)).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testdir::testdir;
+
+    #[test]
+    fn it_parses_signals() {
+        assert_eq!(parse_signal("TERM"), Some(Signal::SIGTERM));
+        assert_eq!(parse_signal("sigint"), Some(Signal::SIGINT));
+        assert_eq!(parse_signal("9"), Some(Signal::SIGKILL));
+        assert_eq!(parse_signal("NOPE"), None);
+    }
+
+    #[test]
+    fn it_tells_reused_pids_apart() {
+        let config = Config::for_tests(testdir!()).unwrap();
+        let mut job = Job::new(&config, "test", std::process::id(), &[]);
+        assert!(job.is_running());
+
+        job.start_time = job.start_time.map(|x| x + 1);
+        assert!(!job.is_running());
+    }
+}
@@ -0,0 +1,248 @@
+use std::env;
+use std::ffi::CString;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::ptr;
+
+use crate::util::KeyFile;
+
+const EXECUTE: u64 = 1 << 0;
+const WRITE_FILE: u64 = 1 << 1;
+const READ_FILE: u64 = 1 << 2;
+const READ_DIR: u64 = 1 << 3;
+const REMOVE_DIR: u64 = 1 << 4;
+const REMOVE_FILE: u64 = 1 << 5;
+const MAKE_CHAR: u64 = 1 << 6;
+const MAKE_DIR: u64 = 1 << 7;
+const MAKE_REG: u64 = 1 << 8;
+const MAKE_SOCK: u64 = 1 << 9;
+const MAKE_FIFO: u64 = 1 << 10;
+const MAKE_BLOCK: u64 = 1 << 11;
+const MAKE_SYM: u64 = 1 << 12;
+/// Since ABI 2
+const REFER: u64 = 1 << 13;
+/// Since ABI 3
+const TRUNCATE: u64 = 1 << 14;
+/// Since ABI 5
+const IOCTL_DEV: u64 = 1 << 15;
+
+/// Rights that apply to files, as opposed to directories
+const FILE_ACCESS: u64 = EXECUTE | WRITE_FILE | READ_FILE | TRUNCATE | IOCTL_DEV;
+const READ_ONLY: u64 = EXECUTE | READ_FILE | READ_DIR;
+const READ_WRITE: u64 = READ_ONLY
+    | WRITE_FILE
+    | REMOVE_DIR
+    | REMOVE_FILE
+    | MAKE_CHAR
+    | MAKE_DIR
+    | MAKE_REG
+    | MAKE_SOCK
+    | MAKE_FIFO
+    | MAKE_BLOCK
+    | MAKE_SYM
+    | REFER
+    | TRUNCATE
+    | IOCTL_DEV;
+
+const CREATE_RULESET_VERSION: libc::c_uint = 1 << 0;
+const RULE_PATH_BENEATH: libc::c_int = 1;
+
+/// What every restricted session can still read, without which hardly any
+/// program starts. Devices are also writable, for `/dev/null` and terminals.
+const SYSTEM: &[(&str, u64)] = &[
+    ("/nix", READ_ONLY),
+    ("/etc", READ_ONLY),
+    ("/run/current-system", READ_ONLY),
+    ("/bin", READ_ONLY),
+    ("/usr", READ_ONLY),
+    ("/proc", READ_ONLY),
+    ("/sys", READ_ONLY),
+    ("/dev", READ_ONLY | WRITE_FILE | IOCTL_DEV),
+];
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// A path that a restricted session may use, with everything below it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub path: PathBuf,
+    pub writable: bool,
+}
+
+/// Parse `PATH`, `PATH:ro` or `PATH:rw`, where a path starting with `~/` is
+/// in the home directory. Without a suffix the path is read-only.
+pub fn parse_rule(text: &str) -> Result<Rule, String> {
+    let (path, writable) = match text.rsplit_once(':') {
+        Some((path, "ro")) => (path, false),
+        Some((path, "rw")) => (path, true),
+        _ => (text, false),
+    };
+    let path = match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    };
+    if !path.is_absolute() {
+        return Err(format!("'{}' is not an absolute path", path.display()));
+    }
+    Ok(Rule { path, writable })
+}
+
+/// The `FsAllow` list of the `[App ID]` group, for exported applications
+pub fn app_rules(settings: &KeyFile, id: &str) -> Result<Vec<Rule>, String> {
+    let group = format!("App {}", id);
+    settings
+        .get_list(&group, "FsAllow")
+        .iter()
+        .map(|x| parse_rule(x).map_err(|err| format!("[{}] FsAllow: {}", group, err)))
+        .collect()
+}
+
+/// Restrict `command` to `rules`, and the system directories, before it
+/// executes. The paths are looked up now, so this goes after `enterns`.
+/// Kernels without Landlock get a warning and no restrictions.
+///
+/// Landlock doesn't cover connecting to Unix sockets, so the host-exec
+/// helper and the service's D-Bus methods that start processes turn away
+/// sessions by the `no_new_privs` flag set here.
+pub fn apply_to(rules: &[Rule], command: &mut Command) -> Result<(), String> {
+    if rules.is_empty() {
+        return Ok(());
+    }
+    let abi = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            ptr::null::<RulesetAttr>(),
+            0,
+            CREATE_RULESET_VERSION,
+        )
+    };
+    if abi < 0 {
+        eprintln!(
+            "warning: Landlock is not available ({}), running without filesystem restrictions",
+            io::Error::last_os_error()
+        );
+        return Ok(());
+    }
+
+    let mut handled = READ_WRITE & !(REFER | TRUNCATE | IOCTL_DEV);
+    for (version, access) in [(2, REFER), (3, TRUNCATE), (5, IOCTL_DEV)] {
+        if abi >= version {
+            handled |= access;
+        }
+    }
+    let attr = RulesetAttr {
+        handled_access_fs: handled,
+    };
+    let ruleset = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr as *const RulesetAttr,
+            std::mem::size_of::<RulesetAttr>(),
+            0,
+        )
+    };
+    if ruleset < 0 {
+        return Err(format!(
+            "Could not create Landlock ruleset: {}",
+            io::Error::last_os_error()
+        ));
+    }
+    let ruleset = unsafe { OwnedFd::from_raw_fd(ruleset as i32) };
+
+    let system = SYSTEM
+        .iter()
+        .map(|(path, access)| (Path::new(path), *access))
+        .filter(|(path, _)| path.exists());
+    let rules = rules.iter().map(|rule| {
+        let access = if rule.writable { READ_WRITE } else { READ_ONLY };
+        (rule.path.as_path(), access)
+    });
+    for (path, access) in system.chain(rules) {
+        add_rule(&ruleset, path, access & handled)
+            .map_err(|err| format!("Cannot allow '{}': {}", path.display(), err))?;
+    }
+
+    unsafe {
+        command.pre_exec(move || {
+            // Landlock needs it, and host-exec goes by it
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) < 0
+                || libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+fn add_rule(ruleset: &OwnedFd, path: &Path, access: u64) -> io::Result<()> {
+    let name = CString::new(path.as_os_str().as_bytes())?;
+    let fd = unsafe { libc::open(name.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    // Directory rights on a file are an error
+    let access = match path.is_dir() {
+        true => access,
+        false => access & FILE_ACCESS,
+    };
+    let attr = PathBeneathAttr {
+        allowed_access: access,
+        parent_fd: fd.as_raw_fd(),
+    };
+    let added = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset.as_raw_fd(),
+            RULE_PATH_BENEATH,
+            &attr as *const PathBeneathAttr,
+            0,
+        )
+    };
+    match added {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host_exec::is_sandboxed;
+
+    #[test]
+    fn it_parses_rules() {
+        let rule = |path: &str, writable| Rule {
+            path: PathBuf::from(path),
+            writable,
+        };
+        assert_eq!(parse_rule("/nix:ro"), Ok(rule("/nix", false)));
+        assert_eq!(parse_rule("/srv/a:b:rw"), Ok(rule("/srv/a:b", true)));
+        assert_eq!(parse_rule("/tmp"), Ok(rule("/tmp", false)));
+        assert!(parse_rule("src:rw").is_err());
+    }
+
+    #[test]
+    fn it_keeps_host_exec_out_of_reach() {
+        let mut command = Command::new("cat");
+        command.arg("/proc/self/status");
+        apply_to(&[parse_rule("/tmp:rw").unwrap()], &mut command).unwrap();
+        let output = command.output().unwrap();
+        assert!(is_sandboxed(&String::from_utf8_lossy(&output.stdout)));
+    }
+}
@@ -0,0 +1,249 @@
+use std::fs;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::time::Duration;
+
+use nix::sys::resource::{getrlimit, setrlimit, Resource, RLIM_INFINITY};
+
+use crate::audit::parse_age;
+use crate::procs::{format_bytes, format_duration};
+use crate::util::KeyFile;
+
+/// A resource limit the box supports, as named on the `run` command line
+/// (as `prlimit` does), in the `[Limits]` group of `brief.conf` and in
+/// `/proc/PID/limits`
+#[derive(Debug)]
+pub struct Limit {
+    name: &'static str,
+    key: &'static str,
+    label: &'static str,
+    resource: Resource,
+    unit: Unit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Unit {
+    Bytes,
+    Count,
+    Seconds,
+}
+
+const LIMITS: &[Limit] = &[
+    Limit {
+        name: "as",
+        key: "AddressSpace",
+        label: "Max address space",
+        resource: Resource::RLIMIT_AS,
+        unit: Unit::Bytes,
+    },
+    // The kernel counts every process of the user against it, those on the
+    // host as well as those in the box
+    Limit {
+        name: "nproc",
+        key: "Processes",
+        label: "Max processes",
+        resource: Resource::RLIMIT_NPROC,
+        unit: Unit::Count,
+    },
+    Limit {
+        name: "nofile",
+        key: "OpenFiles",
+        label: "Max open files",
+        resource: Resource::RLIMIT_NOFILE,
+        unit: Unit::Count,
+    },
+    Limit {
+        name: "cpu",
+        key: "CpuTime",
+        label: "Max cpu time",
+        resource: Resource::RLIMIT_CPU,
+        unit: Unit::Seconds,
+    },
+    Limit {
+        name: "core",
+        key: "CoreSize",
+        label: "Max core file size",
+        resource: Resource::RLIMIT_CORE,
+        unit: Unit::Bytes,
+    },
+];
+
+impl Limit {
+    /// Parse a value: a size like `512M` or `8G`, a count, or a time like
+    /// `90s` or `2h`, depending on the limit. `unlimited` lifts the limit.
+    fn parse(&self, text: &str) -> Result<u64, String> {
+        if text == "unlimited" || text == "infinity" {
+            return Ok(RLIM_INFINITY);
+        }
+        match self.unit {
+            Unit::Bytes => parse_size(text),
+            Unit::Count => text
+                .parse()
+                .map_err(|_| format!("invalid number '{}'", text)),
+            Unit::Seconds => parse_age(text),
+        }
+    }
+
+    fn format(&self, value: u64) -> String {
+        match (value, self.unit) {
+            (RLIM_INFINITY, _) => String::from("unlimited"),
+            (_, Unit::Bytes) => format_bytes(value),
+            (_, Unit::Count) => value.to_string(),
+            (_, Unit::Seconds) => format_duration(Duration::from_secs(value)),
+        }
+    }
+}
+
+/// A size in bytes with an optional binary unit, eg. `512M`
+fn parse_size(text: &str) -> Result<u64, String> {
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => text.split_at(i),
+        None => (text, ""),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", text))?;
+    let shift = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(format!("unknown unit in '{}': use K, M, G or T", text)),
+    };
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("size '{}' is too large", text))
+}
+
+/// Parse `NAME=VALUE`, eg. `nofile=1024`, for the `--limit` option of `run`
+pub fn parse_assignment(text: &str) -> Result<(&'static Limit, u64), String> {
+    let (name, value) = text
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, not '{}'", text))?;
+    let names: Vec<&str> = LIMITS.iter().map(|x| x.name).collect();
+    let limit = LIMITS
+        .iter()
+        .find(|x| x.name == name)
+        .ok_or_else(|| format!("unknown limit '{}': use one of {}", name, names.join(", ")))?;
+    Ok((limit, limit.parse(value)?))
+}
+
+/// Limits to put on processes in the box. Each one is set as both the soft
+/// and the hard limit, so that a process can't raise it again.
+#[derive(Clone, Default)]
+pub struct Limits {
+    values: Vec<(&'static Limit, u64)>,
+}
+
+impl Limits {
+    /// The limits from the `[Limits]` group of the box settings
+    pub fn from_settings(settings: &KeyFile) -> Result<Self, String> {
+        let mut limits = Self::default();
+        for limit in LIMITS {
+            if let Some(value) = settings.get("Limits", limit.key) {
+                let value = limit
+                    .parse(value)
+                    .map_err(|err| format!("[Limits] {}: {}", limit.key, err))?;
+                limits.set(limit, value);
+            }
+        }
+        Ok(limits)
+    }
+
+    pub fn set(&mut self, limit: &'static Limit, value: u64) {
+        self.values.retain(|(x, _)| x.name != limit.name);
+        self.values.push((limit, value));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// As `NAME=VALUE` strings that `parse_assignment` reads back
+    pub fn to_assignments(&self) -> Vec<String> {
+        self.values
+            .iter()
+            .map(|(limit, value)| match *value {
+                RLIM_INFINITY => format!("{}=unlimited", limit.name),
+                value => format!("{}={}", limit.name, value),
+            })
+            .collect()
+    }
+
+    /// Check that the limits are within our own hard limits, which only a
+    /// privileged process could raise
+    pub fn check(&self) -> Result<(), String> {
+        for (limit, value) in &self.values {
+            let (_, hard) = getrlimit(limit.resource)
+                .map_err(|err| format!("Could not get limit {}: {}", limit.name, err))?;
+            if hard != RLIM_INFINITY && (*value == RLIM_INFINITY || *value > hard) {
+                return Err(format!(
+                    "Limit {} of {} is above the hard limit of {}",
+                    limit.name,
+                    limit.format(*value),
+                    limit.format(hard)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the limits of the current process, and with them of the
+    /// processes it starts
+    pub fn apply(&self) -> nix::Result<()> {
+        for (limit, value) in &self.values {
+            setrlimit(limit.resource, *value, *value)?;
+        }
+        Ok(())
+    }
+
+    /// Have `command` set the limits in its process before it executes
+    pub fn apply_to(&self, command: &mut Command) {
+        if self.is_empty() {
+            return;
+        }
+        let limits = self.clone();
+        unsafe {
+            command.pre_exec(move || limits.apply().map_err(io::Error::from));
+        }
+    }
+}
+
+/// The soft limits of `pid` as (name, value) pairs, from `/proc/PID/limits`
+pub fn effective(pid: i32) -> Option<Vec<(&'static str, String)>> {
+    let text = fs::read_to_string(format!("/proc/{}/limits", pid)).ok()?;
+    let mut limits = vec![];
+    for limit in LIMITS {
+        let Some(line) = text.lines().find(|x| x.starts_with(limit.label)) else {
+            continue;
+        };
+        let soft = line[limit.label.len()..].split_whitespace().next()?;
+        let value = match soft.parse() {
+            Ok(value) => limit.format(value),
+            Err(_) => soft.to_string(),
+        };
+        limits.push((limit.name, value));
+    }
+    Some(limits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_limits() {
+        let (limit, value) = parse_assignment("as=2G").unwrap();
+        assert_eq!((limit.key, value), ("AddressSpace", 2 << 30));
+        let (limit, value) = parse_assignment("cpu=10m").unwrap();
+        assert_eq!((limit.key, value), ("CpuTime", 600));
+        let (_, value) = parse_assignment("nofile=unlimited").unwrap();
+        assert_eq!(value, RLIM_INFINITY);
+
+        assert!(parse_assignment("nofile").is_err());
+        assert!(parse_assignment("stack=8M").is_err());
+        assert!(parse_assignment("as=8X").is_err());
+    }
+}
@@ -1,28 +1,55 @@
 mod app;
+mod audit;
+mod autostart;
 mod bind;
 mod command;
 mod config;
+mod dbus;
+mod dbus_proxy;
+mod events;
+mod export;
+mod host_exec;
 mod init;
+mod jobs;
+mod landlock;
+mod limits;
+mod menu;
+mod mime;
+mod open;
+mod output;
+mod procs;
+mod seccomp;
 mod setup;
 mod status;
+mod systemd;
 mod table;
+mod top;
+mod units;
 mod util;
+mod watch;
 
+use std::collections::HashMap;
 use std::env;
 use std::ffi::{OsStr, OsString};
-use std::fs::File;
+use std::fs::{self, File};
 use std::path::{Path, PathBuf};
 use std::process::{exit, ExitCode};
 use std::thread::sleep;
 use std::time::Duration;
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use nix::sched::{setns, CloneFlags};
 use nix::unistd::{chroot, fork, ForkResult};
+use zbus::zvariant::Value;
 
 use crate::command::install;
+use crate::command::{clean_command, command, resolve_workdir, run_command, run_command_pty};
+use crate::config::Config;
+use crate::events::{Event, Session};
 use crate::init::Service;
-use crate::{command::run, config::Config};
+use crate::limits::{Limit, Limits};
+use crate::output::Output;
+use crate::util::{parse_assignment, parse_env_file};
 
 #[derive(Parser, Debug)]
 #[command(name = "nixbox")]
@@ -30,15 +57,86 @@ use crate::{command::run, config::Config};
 struct Cli {
     #[command(subcommand)]
     command: Command,
+
+    #[command(flatten)]
+    output: Output,
+}
+
+#[derive(Args, Debug, Default)]
+struct RunOptions {
+    #[arg(short, long, action)]
+    no_nix_profile: bool,
+
+    /// Run the program on a new pseudo-terminal
+    #[arg(short, long)]
+    tty: bool,
+
+    /// Working directory [default: the current directory]
+    #[arg(short, long)]
+    workdir: Option<PathBuf>,
+
+    /// Set an environment variable
+    #[arg(short, long = "env", value_name = "KEY=VAL")]
+    env: Vec<String>,
+
+    /// Read environment variables from a file of KEY=VAL lines
+    #[arg(long, value_name = "PATH")]
+    env_file: Vec<PathBuf>,
+
+    /// Remove a variable from the environment
+    #[arg(short, long, value_name = "KEY")]
+    unset: Vec<String>,
+
+    /// Don't pass on any environment variables from the host
+    #[arg(long)]
+    clean_env: bool,
+
+    /// Limit a resource, on top of the box's `[Limits]`: as (address space),
+    /// nproc, nofile, cpu (time) or core (dump size), eg. `nofile=1024`.
+    /// nproc counts all processes of the user, in the box or not.
+    #[arg(long, value_name = "NAME=VALUE", value_parser = limits::parse_assignment)]
+    limit: Vec<(&'static Limit, u64)>,
+
+    /// Filter system calls with a seccomp profile: none, default, strict or
+    /// one from the box settings [default: the box's `[Seccomp]` profile]
+    #[arg(long, value_name = "PROFILE")]
+    seccomp: Option<String>,
+
+    /// Only let the command use this path and what is below it, read-only or
+    /// also writable, besides the system directories
+    #[arg(long, value_name = "PATH[:ro|rw]", value_parser = landlock::parse_rule)]
+    fs_allow: Vec<landlock::Rule>,
+
+    /// Set by exported applications, to apply the app's settings and record
+    /// it as such in the history
+    #[arg(long, hide = true)]
+    app: Option<String>,
+
+    /// Run the command through a login shell
+    #[arg(long)]
+    shell: bool,
+
+    /// Run the command in the background, see `ps` and `logs`
+    #[arg(short, long)]
+    detach: bool,
+
+    /// Name of the detached job [default: the name of the command]
+    #[arg(long, requires = "detach")]
+    name: Option<String>,
+
+    /// Set by exported commands, to record them as such in the history
+    #[arg(long, hide = true)]
+    shim: bool,
 }
 
 #[derive(Debug, Subcommand)]
 enum Command {
+    /// Run a command in the box, or the default shell without one
     Run {
-        #[arg(short, long, action)]
-        no_nix_profile: bool,
+        #[command(flatten)]
+        options: RunOptions,
 
-        #[arg()]
+        #[arg(trailing_var_arg = true)]
         rest: Vec<String>,
     },
 
@@ -47,87 +145,393 @@ enum Command {
         command: AppCommand,
     },
 
-    Status,
+    Export {
+        #[command(subcommand)]
+        command: ExportCommand,
+    },
+
+    /// Show the box's processes, namespaces and system
+    Status {
+        /// List what is mounted where in the box instead
+        #[arg(long)]
+        mounts: bool,
+    },
+
+    /// Print the service's events as JSON lines
+    Events {
+        /// Keep printing events as they happen
+        #[arg(short, long)]
+        follow: bool,
+    },
+
+    /// Show the box's processes as they run, like `top`
+    Top {
+        /// Seconds between updates
+        #[arg(short, long, default_value_t = 2.0)]
+        delay: f64,
+    },
+
+    /// List jobs started with `run --detach`, or the box's processes
+    Ps {
+        /// List the processes in the box instead of jobs
+        #[arg(long = "box")]
+        processes: bool,
+
+        /// Only processes of this user
+        #[arg(long, requires = "processes")]
+        user: Option<String>,
+
+        /// Only processes whose command line contains this
+        #[arg(long, requires = "processes")]
+        command: Option<String>,
+    },
+
+    /// Show the commands run in the box, oldest first
+    History {
+        /// Only commands whose command line contains this
+        #[arg(long)]
+        command: Option<String>,
+
+        /// Only commands started this way: run, enter, app, shim, job or dbus
+        #[arg(long)]
+        session: Option<String>,
+
+        /// Only commands run in this directory or below it
+        #[arg(long)]
+        dir: Option<PathBuf>,
+
+        /// Only commands that exited with an error
+        #[arg(long)]
+        failed: bool,
+
+        /// Only commands started within this long, eg. 30m, 2h or 7d
+        #[arg(long, value_name = "AGE", value_parser = audit::parse_age)]
+        since: Option<u64>,
+
+        /// Only the latest N commands
+        #[arg(short = 'n', long, value_name = "N")]
+        limit: Option<usize>,
+    },
+
+    /// Show the output of a job
+    Logs {
+        /// Keep showing new output until the job exits
+        #[arg(short, long)]
+        follow: bool,
+
+        name: String,
+    },
+
+    /// Send a signal to a job or to processes in the box
+    Kill {
+        /// Signal name or number
+        #[arg(short, long, default_value = "TERM")]
+        signal: String,
+
+        /// Signal every process in the box but the service
+        #[arg(long, conflicts_with = "target")]
+        all: bool,
+
+        /// Signal the job NAME
+        #[arg(long, value_name = "NAME", conflicts_with_all = ["target", "all"])]
+        job: Option<String>,
+
+        /// The PID of a box process, or text that the command lines of the
+        /// box processes to signal contain
+        #[arg(required_unless_present_any = ["all", "job"])]
+        target: Option<String>,
+    },
+
+    /// Control the box's systemd user services
+    Unit {
+        #[command(subcommand)]
+        command: UnitCommand,
+    },
+
+    /// Manage the systemd user unit that runs the box
+    #[command(name = "service")]
+    ServiceUnit {
+        #[command(subcommand)]
+        command: ServiceCommand,
+    },
+
     Init,
     Enter,
     Install,
+
+    /// Run a command on the host, outside the box. Needs `HostExec=true` in
+    /// the `[Service]` group of `brief.conf`.
+    HostExec {
+        /// Set an environment variable for the command
+        #[arg(long = "env", value_name = "VAR=VALUE")]
+        envs: Vec<String>,
+
+        /// Working directory of the command [default: the current directory]
+        #[arg(long)]
+        directory: Option<PathBuf>,
+
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        argv: Vec<String>,
+    },
+
+    /// Open a URI or file, used as `xdg-open` and `BROWSER` inside the box
+    #[command(name = "xdg-open", hide = true)]
+    XdgOpen {
+        /// Use an application in the box when one handles the MIME type
+        #[arg(long)]
+        prefer_box: bool,
+
+        target: String,
+    },
 }
 
 #[derive(Debug, Subcommand)]
 enum AppCommand {
     List,
-    Install,
+
+    /// Export applications from the box to the host's application menu
+    Install {
+        /// Make the exported application the host's default for a MIME type
+        #[arg(long, value_name = "MIME_TYPE")]
+        default_for: Vec<String>,
+
+        #[arg(required = true)]
+        ids: Vec<String>,
+    },
+
+    /// Start an application from the box, using D-Bus activation if it
+    /// supports it
+    Run {
+        id: String,
+
+        /// Files or URLs to open
+        uris: Vec<String>,
+    },
 }
 
 impl AppCommand {
-    fn enter(&self) -> ExitCode {
+    fn enter(&self, output: &Output) -> ExitCode {
         let config = Config::new(true).unwrap();
         use AppCommand::*;
         match self {
-            List => app::list(&config),
+            List => app::list(&config, output),
+            Install { ids, default_for } => app::install(&config, ids, default_for),
+
+            Run { id, uris } => {
+                let Some(desktop) = app::find(&config, id) else {
+                    eprintln!("No application with ID '{}' in the box", id);
+                    return ExitCode::FAILURE;
+                };
+                if desktop.dbus_activatable {
+                    match app::activate(&desktop, uris) {
+                        Ok(()) => {
+                            events::emit(
+                                Event::new("app-launched")
+                                    .with("id", id.as_str())
+                                    .with("via", "activation"),
+                            );
+                            return ExitCode::SUCCESS;
+                        }
+                        Err(err) => eprintln!(
+                            "Could not activate '{}' through D-Bus, running it instead: {}",
+                            id, err
+                        ),
+                    }
+                }
 
-            Install => {
-                eprintln!("Not implemented");
-                ExitCode::FAILURE
+                match app::parse_exec(&desktop.exec, uris) {
+                    Some(argv) if !argv.is_empty() => {
+                        events::defer(
+                            Event::new("app-launched")
+                                .with("id", id.as_str())
+                                .with("via", "exec"),
+                        );
+                        let options = RunOptions {
+                            app: Some(id.clone()),
+                            ..RunOptions::default()
+                        };
+                        run_in_box(&argv, &options, "app")
+                    }
+                    _ => {
+                        eprintln!("Invalid Exec key in '{}'", desktop.path.display());
+                        ExitCode::FAILURE
+                    }
+                }
             }
         }
     }
 }
 
+#[derive(Debug, Subcommand)]
+enum UnitCommand {
+    Start {
+        name: String,
+    },
+    Stop {
+        name: String,
+    },
+
+    /// List all units, or show one with the end of its output
+    Status {
+        name: Option<String>,
+    },
+}
+
+impl UnitCommand {
+    fn enter(&self, output: &Output) -> ExitCode {
+        use UnitCommand::*;
+        match self {
+            Start { name } => units::start(name),
+            Stop { name } => units::stop(name),
+            Status { name } => units::status(&Config::new(true).unwrap(), name.as_deref(), output),
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum ServiceCommand {
+    /// Write a user unit for the box, which clients then start through
+    /// systemd instead of forking it themselves
+    Install {
+        /// Also start the box now and on login
+        #[arg(long)]
+        enable: bool,
+    },
+
+    /// Disable and remove the user unit
+    Uninstall,
+}
+
+impl ServiceCommand {
+    fn enter(&self) -> ExitCode {
+        use ServiceCommand::*;
+        match self {
+            Install { enable } => systemd::install(&Config::new(true).unwrap(), *enable),
+            Uninstall => systemd::uninstall(),
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum ExportCommand {
+    /// Create wrappers on the host that run commands from the box
+    Bin {
+        #[arg(short, long)]
+        dir: Option<PathBuf>,
+
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+
+    /// List exported commands
+    List {
+        #[arg(short, long)]
+        dir: Option<PathBuf>,
+    },
+
+    /// Remove exported commands
+    Remove {
+        #[arg(short, long)]
+        dir: Option<PathBuf>,
+
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+
+    /// Find exported commands that no longer exist in the box
+    Check {
+        #[arg(short, long)]
+        dir: Option<PathBuf>,
+    },
+
+    /// Update exported applications and commands to match the box's profile
+    Sync {
+        #[arg(short, long)]
+        dir: Option<PathBuf>,
+    },
+}
+
+impl ExportCommand {
+    fn enter(&self, output: &Output) -> ExitCode {
+        let config = Config::new(true).unwrap();
+        let bindir = |dir: &Option<PathBuf>| {
+            dir.clone()
+                .unwrap_or_else(|| export::default_bindir(&config))
+        };
+        use ExportCommand::*;
+        match self {
+            Bin { dir, names } => export::bin(&config, &bindir(dir), names),
+            List { dir } => export::list(&config, &bindir(dir), output),
+            Remove { dir, names } => export::remove(&bindir(dir), names),
+            Check { dir } => export::check(&config, &bindir(dir)),
+            Sync { dir } => export::sync(&config, &bindir(dir)),
+        }
+    }
+}
+
 fn main() -> ExitCode {
     let cli = Cli::parse();
 
     use Command::*;
     match cli.command {
-        Run {
-            no_nix_profile: _,
-            rest,
-        } => {
-            let service = get_or_init_service();
-            let config = Config::from(&service);
-            enterns(&service);
-
-            let mut shell = PathBuf::from(
-                env::var_os("NIXBOX_SHELL")
-                    .unwrap_or(OsString::from("/run/current-system/sw/bin/bash")),
-            );
-            if shell.is_relative() {
-                shell =
-                    PathBuf::from(env::var_os("HOME").expect("Environment variable HOME not set"))
-                        .join(".nix-profile/bin")
-                        .join(shell);
-            }
+        Run { options, rest } => {
+            let kind = match (options.shim, &options.app) {
+                (true, _) => "shim",
+                (false, Some(_)) => "app",
+                (false, None) => "run",
+            };
+            run_in_box(&rest, &options, kind)
+        }
 
-            let envs = vec![("SHELL", &shell)];
+        App { command } => command.enter(&cli.output),
 
-            run(&config, &rest[0], &rest[1..], envs)
-        }
+        Export { command } => command.enter(&cli.output),
+
+        Unit { command } => command.enter(&cli.output),
 
-        App { command } => command.enter(),
+        ServiceUnit { command } => command.enter(),
 
         Enter => {
             let service = get_or_init_service();
-            let config = Config::new(true).unwrap();
-            enterns(&service);
-
-            let mut shell = PathBuf::from(
-                env::var_os("NIXBOX_SHELL")
-                    .unwrap_or(OsString::from("/run/current-system/sw/bin/bash")),
-            );
-            if shell.is_relative() {
-                shell =
-                    PathBuf::from(env::var_os("HOME").expect("Environment variable HOME not set"))
-                        .join(".nix-profile/bin")
-                        .join(shell);
-            }
+            // With the service's environment, which points at the bus proxy
+            // and the xdg-open shim
+            let config = Config::from(&service);
+            let shell = default_shell();
+            let argv = [
+                "bash",
+                "-lc",
+                AsRef::<OsStr>::as_ref(&shell).to_str().unwrap(),
+            ];
+            let argv = argv.map(String::from);
+            let Some(limits) = box_limits(&config, &[]) else {
+                return ExitCode::FAILURE;
+            };
+            let profile = match seccomp::profile_for(&config.settings, None) {
+                Ok(profile) => profile,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    return ExitCode::FAILURE;
+                }
+            };
+            let mut audit = audit::Entry::start(&config, "enter", &argv);
+            let session = Session::start("enter", &argv);
+            enterns(&service, None);
 
             let envs = vec![("SHELL", &shell)];
-            run(
-                &config,
-                "bash",
-                ["-lc", AsRef::<OsStr>::as_ref(&shell).to_str().unwrap()],
-                envs,
-            )
+            let mut command = command(&config, &argv[0], &argv[1..], envs);
+            limits.apply_to(&mut command);
+            let reporter = session.as_ref().and_then(Session::reporter);
+            if let Some(Err(err)) = profile.map(|x| x.apply_to(&mut command, "enter", reporter)) {
+                eprintln!("Could not set up the seccomp filter: {}", err);
+                return ExitCode::FAILURE;
+            }
+            audit.begin(&env::current_dir().unwrap_or_default());
+            let code = run_command(&mut command);
+            audit.finish(code);
+            if let Some(session) = session {
+                session.finish(code);
+            }
+            ExitCode::from(code as u8)
         }
 
         Init => opt2exit(Service::init()),
@@ -138,8 +542,243 @@ fn main() -> ExitCode {
             install(&config)
         }
 
-        Status => status::status(),
+        Status { mounts: false } => status::status(&cli.output),
+        Status { mounts: true } => status::mounts(&cli.output),
+
+        Top { delay } => top::top(&cli.output, delay),
+
+        Events { follow } => events::show(&Config::new(true).unwrap(), follow),
+
+        Ps {
+            processes: false, ..
+        } => jobs::list(&Config::new(true).unwrap(), &cli.output),
+
+        Ps {
+            processes: true,
+            user,
+            command,
+        } => procs::list(&cli.output, user.as_deref(), command.as_deref()),
+
+        History {
+            command,
+            session,
+            dir,
+            failed,
+            since,
+            limit,
+        } => audit::history(
+            &Config::new(true).unwrap(),
+            &cli.output,
+            &audit::Filter {
+                command,
+                session,
+                dir,
+                failed,
+                since,
+                limit,
+            },
+        ),
+
+        Logs { follow, name } => jobs::logs(&Config::new(true).unwrap(), &name, follow),
+
+        Kill {
+            signal,
+            all,
+            job,
+            target,
+        } => match job {
+            Some(name) => jobs::kill(&Config::new(true).unwrap(), &name, &signal),
+            None => procs::kill(target.as_deref(), all, &signal),
+        },
+
+        HostExec {
+            envs,
+            directory,
+            argv,
+        } => host_exec::host_exec(&argv, &envs, directory.as_deref()),
+
+        XdgOpen { prefer_box, target } => open::open(&target, prefer_box),
+    }
+}
+
+/// Run `rest` in the box, recording it as a session of `kind`
+fn run_in_box(rest: &[String], options: &RunOptions, kind: &str) -> ExitCode {
+    let mut envs: Vec<(String, String)> = vec![];
+    for path in &options.env_file {
+        let vars = fs::read_to_string(path).map(|text| parse_env_file(&text));
+        match vars {
+            Ok(Ok(vars)) => envs.extend(vars),
+            Ok(Err(line)) => {
+                eprintln!("{}:{}: expected KEY=VAL", path.display(), line);
+                return ExitCode::FAILURE;
+            }
+            Err(err) => {
+                eprintln!("Could not read '{}': {}", path.display(), err);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    for var in &options.env {
+        let Some((key, val)) = parse_assignment(var) else {
+            eprintln!("Invalid environment variable '{}': expected KEY=VAL", var);
+            return ExitCode::FAILURE;
+        };
+        envs.push((key.to_string(), val.to_string()));
+    }
+
+    let shell = default_shell();
+    let shell_str = shell.to_string_lossy().into_owned();
+    let argv: Vec<String> = match rest.split_first() {
+        None => vec![String::from("bash"), String::from("-lc"), shell_str.clone()],
+        Some(_) if options.shell => ["bash", "-lc", "exec \"$@\"", "bash"]
+            .iter()
+            .map(|x| x.to_string())
+            .chain(rest.iter().cloned())
+            .collect(),
+        Some(_) => rest.to_vec(),
+    };
+    envs.insert(0, (String::from("SHELL"), shell_str));
+
+    if options.detach {
+        return run_detached(&argv, envs, options);
+    }
+
+    let service = get_or_init_service();
+    let config = Config::from_service(&service, !options.no_nix_profile);
+    let Some(limits) = box_limits(&config, &options.limit) else {
+        return ExitCode::FAILURE;
+    };
+    let profile = match seccomp::profile_for(&config.settings, options.seccomp.as_deref()) {
+        Ok(profile) => profile,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let Some(rules) = fs_rules(&config, options) else {
+        return ExitCode::FAILURE;
+    };
+    let mut audit = audit::Entry::start(&config, kind, &argv);
+    let session = Session::start(kind, &argv);
+    let workdir = options.workdir.as_deref().map(resolve_workdir);
+    enterns(&service, workdir.as_deref());
+
+    let mut command = match options.clean_env {
+        true => clean_command(&config, &argv[0], &argv[1..], envs),
+        false => command(&config, &argv[0], &argv[1..], envs),
+    };
+    for key in &options.unset {
+        command.env_remove(key);
+    }
+    limits.apply_to(&mut command);
+    if let Err(err) = landlock::apply_to(&rules, &mut command) {
+        eprintln!("{}", err);
+        return ExitCode::FAILURE;
+    }
+    let reporter = session.as_ref().and_then(Session::reporter);
+    if let Some(Err(err)) = profile.map(|x| x.apply_to(&mut command, kind, reporter)) {
+        eprintln!("Could not set up the seccomp filter: {}", err);
+        return ExitCode::FAILURE;
+    }
+
+    audit.begin(&env::current_dir().unwrap_or_default());
+    let code = match options.tty {
+        true => run_command_pty(&mut command),
+        false => run_command(&mut command),
+    };
+    audit.finish(code);
+    if let Some(session) = session {
+        session.finish(code);
+    }
+    ExitCode::from(code as u8)
+}
+
+/// Have the service start `argv` as a job, which keeps running after we exit
+fn run_detached(argv: &[String], envs: Vec<(String, String)>, options: &RunOptions) -> ExitCode {
+    if options.tty || options.no_nix_profile {
+        eprintln!("--tty and --no-nix-profile cannot be used with --detach");
+        return ExitCode::FAILURE;
+    }
+
+    let service = get_or_init_service();
+    let config = Config::from(&service);
+    let name = match &options.name {
+        Some(name) => name.clone(),
+        None => jobs::unused_name(&config, &argv[0]),
+    };
+    // Relative to here, not to the service's working directory
+    let cwd = match &options.workdir {
+        Some(dir) => resolve_workdir(dir),
+        None => env::current_dir().expect("cannot get current working directory"),
+    };
+
+    let mut job_options: HashMap<&str, Value> = HashMap::new();
+    job_options.insert("clean-env", Value::from(options.clean_env));
+    job_options.insert("unset", Value::from(options.unset.clone()));
+    // The service applies the box's own limits to everything it starts
+    let mut limits = Limits::default();
+    for (limit, value) in &options.limit {
+        limits.set(limit, *value);
+    }
+    job_options.insert("limits", Value::from(limits.to_assignments()));
+    if let Some(profile) = &options.seccomp {
+        job_options.insert("seccomp", Value::from(profile.as_str()));
+    }
+    let Some(rules) = fs_rules(&config, options) else {
+        return ExitCode::FAILURE;
+    };
+    let rules: Vec<String> = rules
+        .iter()
+        .map(|x| {
+            format!(
+                "{}:{}",
+                x.path.display(),
+                if x.writable { "rw" } else { "ro" }
+            )
+        })
+        .collect();
+    job_options.insert("fs-allow", Value::from(rules));
+    jobs::start(&name, argv, &envs.into_iter().collect(), &cwd, job_options)
+}
+
+/// The box's `[Limits]` with `overrides` on top, or `None` after reporting
+/// why they can't be applied
+fn box_limits(config: &Config, overrides: &[(&'static Limit, u64)]) -> Option<Limits> {
+    let mut limits = Limits::from_settings(&config.settings)
+        .map_err(|err| eprintln!("Invalid box settings: {}", err))
+        .ok()?;
+    for (limit, value) in overrides {
+        limits.set(limit, *value);
+    }
+    limits.check().map_err(|err| eprintln!("{}", err)).ok()?;
+    Some(limits)
+}
+
+/// The paths of `--fs-allow` and, for an app, of its `FsAllow` setting, or
+/// `None` after reporting why they are invalid
+fn fs_rules(config: &Config, options: &RunOptions) -> Option<Vec<landlock::Rule>> {
+    let mut rules = options.fs_allow.clone();
+    if let Some(id) = &options.app {
+        let app_rules = landlock::app_rules(&config.settings, id)
+            .map_err(|err| eprintln!("Invalid box settings: {}", err))
+            .ok()?;
+        rules.extend(app_rules);
+    }
+    Some(rules)
+}
+
+/// The user's shell in the box, from `NIXBOX_SHELL`. Relative names are
+/// looked up in the user's nix profile.
+fn default_shell() -> PathBuf {
+    let shell = PathBuf::from(
+        env::var_os("NIXBOX_SHELL").unwrap_or(OsString::from("/run/current-system/sw/bin/bash")),
+    );
+    if shell.is_relative() {
+        return PathBuf::from(env::var_os("HOME").expect("Environment variable HOME not set"))
+            .join(".nix-profile/bin")
+            .join(shell);
     }
+    shell
 }
 
 fn get_or_init_service() -> Service {
@@ -147,6 +786,15 @@ fn get_or_init_service() -> Service {
         return service;
     }
 
+    // Under systemd the box gets its own cgroup, instead of living in the
+    // session of whichever command started it
+    if systemd::is_installed() {
+        if !systemd::systemctl(&["start", &systemd::unit_name()]) {
+            exit(1);
+        }
+        return wait_for_service();
+    }
+
     match unsafe { fork() } {
         Ok(ForkResult::Parent { .. }) => wait_for_service(),
         Ok(ForkResult::Child) => match unsafe { fork() } {
@@ -161,7 +809,10 @@ fn get_or_init_service() -> Service {
     }
 }
 
-fn enterns(service: &Service) {
+/// Join the box's namespaces and chroot. The working directory is `workdir`,
+/// or the current directory when it exists in the box and the home directory
+/// otherwise.
+fn enterns(service: &Service, workdir: Option<&Path>) {
     let cwd = env::current_dir().expect("cannot get current working directory");
     let ns = Path::new("/proc").join(service.pid.to_string()).join("ns");
     if !ns.exists() {
@@ -179,9 +830,24 @@ fn enterns(service: &Service) {
     env::set_current_dir("/").expect("cannot change directory to /");
     chroot(&service.root)
         .unwrap_or_else(|err| panic!("chroot({}): {}", service.root.display(), err));
-    env::set_current_dir(&cwd).unwrap_or_else(|err| {
-        eprintln!("cannot change directory back to {}: {}", cwd.display(), err)
-    });
+    if let Some(workdir) = workdir {
+        if let Err(err) = env::set_current_dir(workdir) {
+            eprintln!("cannot change directory to {}: {}", workdir.display(), err);
+            exit(1);
+        }
+        return;
+    }
+    if let Err(err) = env::set_current_dir(&cwd) {
+        let home = PathBuf::from(env::var_os("HOME").unwrap_or(OsString::from("/")));
+        eprintln!(
+            "warning: {} is not available in the box ({}), starting in {} instead",
+            cwd.display(),
+            err,
+            home.display()
+        );
+        env::set_current_dir(&home)
+            .unwrap_or_else(|_| env::set_current_dir("/").expect("cannot change directory to /"));
+    }
 }
 
 fn wait_for_service() -> Service {
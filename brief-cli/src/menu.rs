@@ -0,0 +1,75 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use crate::config::{host_config_home, host_data_home, BOX_NAME};
+
+/// Desktop entry category that files exported applications under the box's
+/// submenu
+pub fn category() -> String {
+    format!("X-Brief-{}", BOX_NAME)
+}
+
+/// Add a submenu for the box to the host's application menu
+pub fn install() -> io::Result<()> {
+    let name = format!("brief-{}", BOX_NAME);
+
+    let directory = directory_path();
+    fs::create_dir_all(directory.parent().unwrap())?;
+    File::create(&directory)?.write_all(
+        format!(
+            "[Desktop Entry]\n\
+             Type=Directory\n\
+             Name={box}\n\
+             Comment=Applications in the {box} box\n\
+             Icon=utilities-terminal\n",
+            box = BOX_NAME
+        )
+        .as_bytes(),
+    )?;
+
+    let menu = menu_path();
+    fs::create_dir_all(menu.parent().unwrap())?;
+    File::create(&menu)?.write_all(
+        format!(
+            "<!DOCTYPE Menu PUBLIC \"-//freedesktop//DTD Menu 1.0//EN\"\n \
+             \"http://www.freedesktop.org/standards/menu-spec/1.0/menu.dtd\">\n\
+             <Menu>\n  \
+               <Name>Applications</Name>\n  \
+               <Menu>\n    \
+                 <Name>{name}</Name>\n    \
+                 <Directory>{name}.directory</Directory>\n    \
+                 <Include>\n      \
+                   <Category>{category}</Category>\n    \
+                 </Include>\n  \
+               </Menu>\n\
+             </Menu>\n",
+            name = name,
+            category = category()
+        )
+        .as_bytes(),
+    )
+}
+
+/// Remove the box's submenu, e.g. once nothing is exported anymore
+pub fn uninstall() -> io::Result<()> {
+    for path in [menu_path(), directory_path()] {
+        match fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+fn menu_path() -> PathBuf {
+    host_config_home()
+        .join("menus/applications-merged")
+        .join(format!("brief-{}.menu", BOX_NAME))
+}
+
+fn directory_path() -> PathBuf {
+    host_data_home()
+        .join("desktop-directories")
+        .join(format!("brief-{}.directory", BOX_NAME))
+}
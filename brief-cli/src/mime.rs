@@ -0,0 +1,230 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+
+use crate::config::{host_config_home, host_data_home, Config, BOX_NAME};
+use crate::util::KeyFile;
+
+const DEFAULT_APPLICATIONS: &str = "Default Applications";
+
+/// Make `desktop_id` the default application for `mime_type` in the host's
+/// `mimeapps.list`
+pub fn set_default(mime_type: &str, desktop_id: &str) -> io::Result<()> {
+    let dir = host_config_home();
+    fs::create_dir_all(&dir)?;
+
+    let path = dir.join("mimeapps.list");
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+    File::create(&path)?
+        .write_all(set_key(&text, DEFAULT_APPLICATIONS, mime_type, desktop_id).as_bytes())
+}
+
+/// Regenerate `mimeinfo.cache` in `appdir`, like `update-desktop-database`
+/// does, so that exported applications show up in "Open With" menus.
+pub fn update_cache(appdir: &Path) -> io::Result<()> {
+    let mut cache: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    collect_mime_types(appdir, "", &mut cache)?;
+
+    let mut text = String::from("[MIME Cache]\n");
+    for (mime_type, ids) in cache {
+        text.push_str(&mime_type);
+        text.push('=');
+        for id in ids {
+            text.push_str(&id);
+            text.push(';');
+        }
+        text.push('\n');
+    }
+    File::create(appdir.join("mimeinfo.cache"))?.write_all(text.as_bytes())
+}
+
+fn collect_mime_types(
+    dir: &Path,
+    prefix: &str,
+    cache: &mut BTreeMap<String, BTreeSet<String>>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(file_name) = path.file_name().and_then(|x| x.to_str()) else {
+            continue;
+        };
+
+        // Desktop file IDs of entries in subdirectories are prefixed with
+        // the directory name, see the desktop entry specification
+        if path.is_dir() {
+            collect_mime_types(&path, &format!("{}{}-", prefix, file_name), cache)?;
+            continue;
+        }
+        if !file_name.ends_with(".desktop") {
+            continue;
+        }
+
+        let Ok(keyfile) = KeyFile::parse_file(&path) else {
+            continue;
+        };
+        if keyfile.get_bool("Desktop Entry", "Hidden") == Some(true) {
+            continue;
+        }
+        for mime_type in keyfile.get_list("Desktop Entry", "MimeType") {
+            cache
+                .entry(mime_type)
+                .or_default()
+                .insert(format!("{}{}", prefix, file_name));
+        }
+    }
+    Ok(())
+}
+
+/// Copy the shared-mime-info definitions from the box's profile to the host
+/// and rebuild the host's MIME database if that changed any. Definitions
+/// that were imported earlier but are no longer in the profile are removed.
+pub fn import_definitions(config: &Config) -> io::Result<()> {
+    let prefix = format!("brief-{}-", BOX_NAME);
+    let mimedir = host_data_home().join("mime");
+    let packagedir = mimedir.join("packages");
+    fs::create_dir_all(&packagedir)?;
+
+    let mut imported = BTreeSet::new();
+    let mut changed = false;
+    let sources = config
+        .nix_profile
+        .as_ref()
+        .and_then(|x| config.resolve_symlink(x.join("share/mime/packages")).ok())
+        .and_then(|x| fs::read_dir(x).ok());
+    for entry in sources.into_iter().flatten() {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+        if !file_name.ends_with(".xml") {
+            continue;
+        }
+
+        let source = config.resolve_symlink(entry.path())?;
+        let target = format!("{}{}", prefix, file_name);
+        let contents = fs::read(source)?;
+        let path = packagedir.join(&target);
+        if fs::read(&path).ok().as_ref() != Some(&contents) {
+            fs::write(&path, &contents)?;
+            changed = true;
+        }
+        imported.insert(target);
+    }
+
+    for entry in fs::read_dir(&packagedir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+        if file_name.starts_with(&prefix) && !imported.contains(file_name) {
+            fs::remove_file(entry.path())?;
+            changed = true;
+        }
+    }
+    if !changed {
+        return Ok(());
+    }
+
+    match Command::new("update-mime-database").arg(&mimedir).status() {
+        Ok(status) if !status.success() => {
+            eprintln!("update-mime-database failed: {}", status);
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            eprintln!("Warning: update-mime-database not found on the host");
+        }
+        Err(err) => return Err(err),
+        Ok(_) => (),
+    }
+    Ok(())
+}
+
+/// Set `key` in `group` of a key file, keeping everything else (comments,
+/// order, other groups) intact.
+fn set_key(text: &str, group: &str, key: &str, val: &str) -> String {
+    let header = format!("[{}]", group);
+    let entry = format!("{}={}", key, val);
+
+    let mut lines: Vec<String> = text.lines().map(String::from).collect();
+    let Some(start) = lines.iter().position(|line| line.trim() == header) else {
+        if lines.last().map(|x| !x.trim().is_empty()).unwrap_or(false) {
+            lines.push(String::new());
+        }
+        lines.push(header);
+        lines.push(entry);
+        return lines.join("\n") + "\n";
+    };
+
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .map(|x| x + start + 1)
+        .unwrap_or(lines.len());
+
+    let existing = lines[start + 1..end].iter().position(|line| {
+        line.split_once('=')
+            .map(|(k, _)| k.trim() == key)
+            .unwrap_or(false)
+    });
+    match existing {
+        Some(index) => lines[start + 1 + index] = entry,
+        None => {
+            // Insert after the last entry of the group, before blank lines
+            let mut index = end;
+            while index > start + 1 && lines[index - 1].trim().is_empty() {
+                index -= 1;
+            }
+            lines.insert(index, entry);
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_adds_missing_group() {
+        let actual = set_key("", "Default Applications", "text/plain", "a.desktop");
+        assert_eq!(actual, "[Default Applications]\ntext/plain=a.desktop\n");
+
+        let actual = set_key(
+            "[Added Associations]\ntext/plain=b.desktop;\n",
+            "Default Applications",
+            "text/plain",
+            "a.desktop",
+        );
+        assert_eq!(
+            actual,
+            "[Added Associations]\ntext/plain=b.desktop;\n\n[Default Applications]\ntext/plain=a.desktop\n"
+        );
+    }
+
+    #[test]
+    fn it_replaces_existing_key() {
+        let text = "[Default Applications]\ntext/plain=b.desktop\ntext/html=c.desktop\n";
+        let actual = set_key(text, "Default Applications", "text/plain", "a.desktop");
+        assert_eq!(
+            actual,
+            "[Default Applications]\ntext/plain=a.desktop\ntext/html=c.desktop\n"
+        );
+    }
+
+    #[test]
+    fn it_appends_to_existing_group() {
+        let text = "[Default Applications]\ntext/html=c.desktop\n\n[Removed Associations]\n";
+        let actual = set_key(text, "Default Applications", "text/plain", "a.desktop");
+        assert_eq!(
+            actual,
+            "[Default Applications]\ntext/html=c.desktop\ntext/plain=a.desktop\n\n[Removed Associations]\n"
+        );
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, Stdio};
+
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::{Fd, Value};
+
+use crate::app::parse_exec;
+use crate::config::Config;
+use crate::export::shell_quote;
+use crate::util::KeyFile;
+
+const PORTAL_NAME: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const PORTAL_OPEN_URI: &str = "org.freedesktop.portal.OpenURI";
+
+/// Write an `xdg-open` replacement into `dir` that forwards to `nixbox
+/// xdg-open`, put it first on the box's PATH and make it the box's BROWSER
+pub fn install_shim(config: &mut Config, dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let path = dir.join("xdg-open");
+    let prefer_box = match config.settings.get_bool("Open", "PreferBox") {
+        Some(true) => " --prefer-box",
+        _ => "",
+    };
+
+    let mut file = File::create(&path)?;
+    writeln!(file, "#!/bin/sh")?;
+    writeln!(file, "# Generated by brief. Do not edit.")?;
+    writeln!(
+        file,
+        "exec {} xdg-open{} -- \"$@\"",
+        shell_quote(&config.nixbox_executable().to_string_lossy()),
+        prefer_box
+    )?;
+    let mut perms = file.metadata()?.permissions();
+    perms.set_mode(0o755);
+    fs::set_permissions(&path, perms)?;
+
+    let mut search_path = dir.as_os_str().to_owned();
+    if let Some(old) = config.env.get(OsStr::new("PATH")) {
+        search_path.push(":");
+        search_path.push(old);
+    }
+    config.env.insert("PATH".into(), search_path);
+    config.env.insert("BROWSER".into(), path.into());
+    Ok(())
+}
+
+/// Open a URI or file from inside the box. With `prefer_box`, an application
+/// in the box that handles the target's MIME type is used when there is one;
+/// otherwise the request is sent to the host through the OpenURI portal.
+pub fn open(target: &str, prefer_box: bool) -> ExitCode {
+    if prefer_box {
+        if let Some(argv) = find_handler(&mime_type(target))
+            .and_then(|exec| parse_exec(&exec, &[target.to_string()]))
+            .filter(|argv| !argv.is_empty())
+        {
+            return match Command::new(&argv[0])
+                .args(&argv[1..])
+                .stdin(Stdio::null())
+                .spawn()
+            {
+                Ok(_) => ExitCode::SUCCESS,
+                Err(err) => {
+                    eprintln!("failed to execute {}: {}", argv[0], err);
+                    ExitCode::FAILURE
+                }
+            };
+        }
+    }
+
+    match open_on_host(target) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Could not open '{}' on the host: {}", target, err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn open_on_host(target: &str) -> zbus::Result<()> {
+    let conn = Connection::session()?;
+    let proxy = Proxy::new(&conn, PORTAL_NAME, PORTAL_PATH, PORTAL_OPEN_URI)?;
+
+    let mut options: HashMap<&str, Value> = HashMap::new();
+    if let Ok(token) = env::var("XDG_ACTIVATION_TOKEN") {
+        options.insert("activation_token", Value::from(token));
+    }
+
+    // The portal refuses file:// URIs, local files are passed by descriptor
+    match local_path(target) {
+        Some(path) => {
+            let file = File::open(path)?;
+            proxy.call_method("OpenFile", &("", Fd::from(&file), options))?;
+        }
+        None => {
+            proxy.call_method("OpenURI", &("", target, options))?;
+        }
+    }
+    Ok(())
+}
+
+/// Path of a `file://` URI or plain path. `None` for other URIs.
+fn local_path(target: &str) -> Option<PathBuf> {
+    if let Some(path) = target.strip_prefix("file://") {
+        // Only local files, ie. an empty or "localhost" host part
+        let path = path.strip_prefix("localhost").unwrap_or(path);
+        if !path.starts_with('/') {
+            return None;
+        }
+        return Some(PathBuf::from(OsStr::from_bytes(&percent_decode(path))));
+    }
+    match scheme(target) {
+        Some(_) => None,
+        None => Some(PathBuf::from(target)),
+    }
+}
+
+fn scheme(target: &str) -> Option<&str> {
+    let (scheme, _) = target.split_once(':')?;
+    let valid = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    Some(scheme).filter(|_| valid)
+}
+
+fn percent_decode(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let byte = bytes
+            .get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match byte {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Data directories of the box, in order of precedence
+fn data_dirs() -> Vec<PathBuf> {
+    let home = env::var_os("HOME").expect("Environment variable HOME not set");
+    let mut dirs = vec![env::var_os("XDG_DATA_HOME")
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(&home).join(".local/share"))];
+    match env::var_os("XDG_DATA_DIRS").filter(|x| !x.is_empty()) {
+        Some(val) => dirs.extend(env::split_paths(&val)),
+        None => {
+            dirs.push(PathBuf::from(&home).join(".nix-profile/share"));
+            dirs.push(PathBuf::from("/run/current-system/sw/share"));
+        }
+    }
+    dirs
+}
+
+fn mime_type(target: &str) -> String {
+    let Some(path) = local_path(target) else {
+        let scheme = scheme(target).unwrap_or_default();
+        return format!("x-scheme-handler/{}", scheme.to_ascii_lowercase());
+    };
+    if path.is_dir() {
+        return String::from("inode/directory");
+    }
+
+    let file_name = path
+        .file_name()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let globs: Vec<String> = data_dirs()
+        .iter()
+        .filter_map(|dir| fs::read_to_string(dir.join("mime/globs2")).ok())
+        .collect();
+    mime_from_globs(globs.iter().flat_map(|x| x.lines()), &file_name)
+        .unwrap_or_else(|| String::from("application/octet-stream"))
+}
+
+/// Look `file_name` up in shared-mime-info `globs2` lines. The highest weight
+/// wins, then the longest pattern. Only literal names and `*.ext` style
+/// patterns are supported.
+fn mime_from_globs<'a>(lines: impl Iterator<Item = &'a str>, file_name: &str) -> Option<String> {
+    let lowercase = file_name.to_lowercase();
+    let mut best: Option<(u32, usize, &str)> = None;
+
+    for line in lines {
+        if line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split(':');
+        let (Some(weight), Some(mime_type), Some(glob)) = (
+            fields.next().and_then(|x| x.parse::<u32>().ok()),
+            fields.next(),
+            fields.next(),
+        ) else {
+            continue;
+        };
+        let case_sensitive = fields.next().map(|x| x.contains("cs")).unwrap_or(false);
+        let name = match case_sensitive {
+            true => file_name,
+            false => &lowercase,
+        };
+        let glob_cased = match case_sensitive {
+            true => glob.to_string(),
+            false => glob.to_lowercase(),
+        };
+
+        let matched = match glob_cased.strip_prefix('*') {
+            Some(suffix) if !suffix.contains(['*', '?', '[']) => name.ends_with(suffix),
+            Some(_) => false,
+            None => !glob_cased.contains(['*', '?', '[']) && name == glob_cased,
+        };
+        if matched
+            && best
+                .map(|x| (weight, glob.len()) > (x.0, x.1))
+                .unwrap_or(true)
+        {
+            best = Some((weight, glob.len(), mime_type));
+        }
+    }
+    best.map(|x| x.2.to_string())
+}
+
+/// Exec line of the box's application for `mime_type`: the default from
+/// `mimeapps.list`, otherwise the first application that lists the type
+fn find_handler(mime_type: &str) -> Option<String> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            PathBuf::from(env::var_os("HOME").expect("Environment variable HOME not set"))
+                .join(".config")
+        });
+    let appdirs: Vec<PathBuf> = data_dirs()
+        .iter()
+        .map(|dir| dir.join("applications"))
+        .collect();
+
+    let default = KeyFile::parse_file(config_home.join("mimeapps.list"))
+        .ok()
+        .and_then(|x| {
+            x.get_list("Default Applications", mime_type)
+                .into_iter()
+                .next()
+        });
+    if let Some(id) = default {
+        let exec = appdirs.iter().find_map(|dir| {
+            KeyFile::parse_file(dir.join(&id))
+                .ok()?
+                .get("Desktop Entry", "Exec")
+                .map(String::from)
+        });
+        if exec.is_some() {
+            return exec;
+        }
+    }
+
+    for dir in appdirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        let mut paths: Vec<PathBuf> = entries.filter_map(|x| x.ok()).map(|x| x.path()).collect();
+        paths.sort();
+        for path in paths {
+            if path.extension() != Some(OsStr::new("desktop")) {
+                continue;
+            }
+            let Ok(keyfile) = KeyFile::parse_file(&path) else {
+                continue;
+            };
+            if keyfile.get_bool("Desktop Entry", "Hidden") == Some(true) {
+                continue;
+            }
+            if keyfile
+                .get_list("Desktop Entry", "MimeType")
+                .iter()
+                .any(|x| x == mime_type)
+            {
+                if let Some(exec) = keyfile.get("Desktop Entry", "Exec") {
+                    return Some(exec.to_string());
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GLOBS: &str = "\
+# This file was automatically generated
+50:text/plain:*.txt
+50:application/x-compressed-tar:*.tar.gz
+50:application/gzip:*.gz
+50:text/x-makefile:makefile
+10:text/x-c++src:*.C:cs
+";
+
+    #[test]
+    fn it_matches_mime_globs() {
+        let find = |name| mime_from_globs(GLOBS.lines(), name);
+
+        assert_eq!(find("notes.TXT").as_deref(), Some("text/plain"));
+        assert_eq!(
+            find("src.tar.gz").as_deref(),
+            Some("application/x-compressed-tar")
+        );
+        assert_eq!(find("Makefile").as_deref(), Some("text/x-makefile"));
+        assert_eq!(find("main.C").as_deref(), Some("text/x-c++src"));
+        assert_eq!(find("main.c"), None);
+    }
+
+    #[test]
+    fn it_finds_local_paths() {
+        assert_eq!(
+            local_path("file:///tmp/a%20b.txt"),
+            Some(PathBuf::from("/tmp/a b.txt"))
+        );
+        assert_eq!(local_path("notes.txt"), Some(PathBuf::from("notes.txt")));
+        assert_eq!(local_path("https://example.com/"), None);
+        assert_eq!(local_path("file://otherhost/tmp/a"), None);
+    }
+}
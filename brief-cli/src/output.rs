@@ -0,0 +1,316 @@
+use std::cmp::Ordering;
+use std::process::ExitCode;
+
+use clap::{Args, ValueEnum};
+
+use crate::procs::format_bytes;
+use crate::table::Table;
+
+/// Options of listing commands, global so they can go anywhere on the
+/// command line
+#[derive(Args, Clone, Debug, Default)]
+pub struct Output {
+    /// Output format of commands that list things
+    #[arg(long = "output", global = true, value_enum, default_value_t)]
+    pub format: Format,
+
+    /// Leave out the header line of tables and TSV
+    #[arg(long, global = true)]
+    pub no_headers: bool,
+
+    /// Fields to show, separated by commas
+    #[arg(long, global = true, value_delimiter = ',', value_name = "FIELDS")]
+    pub columns: Vec<String>,
+
+    /// Sort by a field, in descending order when it starts with `-`
+    #[arg(long, global = true, value_name = "FIELD", allow_hyphen_values = true)]
+    pub sort: Option<String>,
+}
+
+/// How listing commands print their results, set with the global `--output`
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Format {
+    /// Aligned columns for people
+    #[default]
+    Table,
+    /// A JSON array of objects
+    Json,
+    /// One JSON object per line
+    Jsonl,
+    /// Tab-separated values with a header line of field names
+    Tsv,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Field {
+    Text(String),
+    Number(i64),
+    Float(f64),
+    /// A size in bytes, shown with a unit in tables
+    Bytes(u64),
+    Null,
+}
+
+impl From<String> for Field {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+impl From<&str> for Field {
+    fn from(text: &str) -> Self {
+        Self::Text(text.to_string())
+    }
+}
+
+impl<T: Into<Field>> From<Option<T>> for Field {
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(Self::Null)
+    }
+}
+
+impl From<i64> for Field {
+    fn from(number: i64) -> Self {
+        Self::Number(number)
+    }
+}
+
+impl From<i32> for Field {
+    fn from(number: i32) -> Self {
+        Self::Number(number.into())
+    }
+}
+
+impl From<u32> for Field {
+    fn from(number: u32) -> Self {
+        Self::Number(number.into())
+    }
+}
+
+/// Rows with named fields, printed as a table or in a machine-readable
+/// format. Field names are part of the output format, so keep them stable;
+/// table headers are free to change.
+pub struct Listing {
+    /// (field name, table header). Unless asked for with `--columns`, fields
+    /// with an empty header are left out of the table.
+    columns: Vec<(&'static str, &'static str)>,
+    rows: Vec<Vec<Field>>,
+    /// Fields that are shortened to fit the terminal
+    truncate: Vec<&'static str>,
+}
+
+impl Listing {
+    pub fn new(columns: &[(&'static str, &'static str)]) -> Self {
+        Self {
+            columns: columns.to_vec(),
+            rows: vec![],
+            truncate: vec![],
+        }
+    }
+
+    /// Shorten `fields` in tables that are wider than the terminal
+    pub fn truncate(&mut self, fields: &[&'static str]) {
+        self.truncate.extend_from_slice(fields);
+    }
+
+    pub fn add_row(&mut self, row: Vec<Field>) {
+        debug_assert_eq!(row.len(), self.columns.len());
+        self.rows.push(row);
+    }
+
+    /// Print the rows as `output` asks, failing on unknown field names
+    pub fn print(mut self, output: &Output) -> ExitCode {
+        if let Some(sort) = &output.sort {
+            let (field, descending) = match sort.strip_prefix('-') {
+                Some(field) => (field, true),
+                None => (sort.as_str(), false),
+            };
+            let Some(index) = self.field_index(field) else {
+                return self.unknown_field(field);
+            };
+            self.rows.sort_by(|a, b| match descending {
+                true => compare(&b[index], &a[index]),
+                false => compare(&a[index], &b[index]),
+            });
+        }
+
+        let mut selected = vec![];
+        for field in &output.columns {
+            match self.field_index(field) {
+                Some(index) => selected.push(index),
+                None => return self.unknown_field(field),
+            }
+        }
+        if selected.is_empty() {
+            selected = (0..self.columns.len())
+                .filter(|x| output.format != Format::Table || !self.columns[*x].1.is_empty())
+                .collect();
+        }
+        self.columns = selected.iter().map(|x| self.columns[*x]).collect();
+        self.rows = self
+            .rows
+            .iter()
+            .map(|row| selected.iter().map(|x| row[*x].clone()).collect())
+            .collect();
+
+        match output.format {
+            Format::Table => {
+                let mut table = Table::new();
+                for (index, (name, header)) in self.columns.iter().enumerate() {
+                    match header.is_empty() {
+                        true => table.add_header(name.to_uppercase()),
+                        false => table.add_header(header.to_string()),
+                    }
+                    if self.truncate.contains(name) {
+                        table.truncate(index);
+                    }
+                }
+                if output.no_headers {
+                    table.hide_headers();
+                }
+                for row in &self.rows {
+                    table.add_row(row.iter().map(|x| to_text(x, true)).collect());
+                }
+                table.print();
+            }
+            Format::Json => {
+                let objects: Vec<String> = self.rows.iter().map(|x| self.to_json(x)).collect();
+                println!("[{}]", objects.join(","));
+            }
+            Format::Jsonl => {
+                for row in &self.rows {
+                    println!("{}", self.to_json(row));
+                }
+            }
+            Format::Tsv => {
+                if !output.no_headers {
+                    let names: Vec<&str> = self.columns.iter().map(|x| x.0).collect();
+                    println!("{}", names.join("\t"));
+                }
+                for row in &self.rows {
+                    let cells: Vec<String> =
+                        row.iter().map(|x| escape_tsv(&to_text(x, false))).collect();
+                    println!("{}", cells.join("\t"));
+                }
+            }
+        }
+        ExitCode::SUCCESS
+    }
+
+    fn field_index(&self, field: &str) -> Option<usize> {
+        self.columns.iter().position(|x| x.0 == field)
+    }
+
+    fn unknown_field(&self, field: &str) -> ExitCode {
+        let names: Vec<&str> = self.columns.iter().map(|x| x.0).collect();
+        eprintln!(
+            "Unknown field '{}', expected one of: {}",
+            field,
+            names.join(", ")
+        );
+        ExitCode::FAILURE
+    }
+
+    fn to_json(&self, row: &[Field]) -> String {
+        let members: Vec<String> = self
+            .columns
+            .iter()
+            .zip(row)
+            .map(|((name, _), field)| format!("{}:{}", json_string(name), json_value(field)))
+            .collect();
+        format!("{{{}}}", members.join(","))
+    }
+}
+
+/// Numbers sort numerically and before text, empty fields go last
+fn compare(a: &Field, b: &Field) -> Ordering {
+    match (a, b) {
+        (Field::Text(a), Field::Text(b)) => a.cmp(b),
+        (Field::Null, Field::Null) => Ordering::Equal,
+        (Field::Null, _) => Ordering::Greater,
+        (_, Field::Null) => Ordering::Less,
+        (Field::Text(_), _) => Ordering::Greater,
+        (_, Field::Text(_)) => Ordering::Less,
+        (a, b) => to_number(a).total_cmp(&to_number(b)),
+    }
+}
+
+fn to_number(field: &Field) -> f64 {
+    match field {
+        Field::Number(number) => *number as f64,
+        Field::Float(number) => *number,
+        Field::Bytes(bytes) => *bytes as f64,
+        Field::Text(_) | Field::Null => f64::NAN,
+    }
+}
+
+/// Text of a table cell when `human`, otherwise of a TSV cell
+fn to_text(field: &Field, human: bool) -> String {
+    match field {
+        Field::Text(text) => text.clone(),
+        Field::Number(number) => number.to_string(),
+        Field::Float(number) => format!("{:.1}", number),
+        Field::Bytes(bytes) if human => format_bytes(*bytes),
+        Field::Bytes(bytes) => bytes.to_string(),
+        Field::Null => String::new(),
+    }
+}
+
+pub fn json_value(field: &Field) -> String {
+    match field {
+        Field::Text(text) => json_string(text),
+        Field::Number(number) => number.to_string(),
+        Field::Float(number) if number.is_finite() => number.to_string(),
+        Field::Float(_) => String::from("null"),
+        Field::Bytes(bytes) => bytes.to_string(),
+        Field::Null => String::from("null"),
+    }
+}
+
+pub fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Escape the characters that would break a TSV line, the way
+/// `COPY ... TO` in PostgreSQL and most TSV readers expect
+fn escape_tsv(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_encodes_json() {
+        let mut listing = Listing::new(&[("id", "ID"), ("pid", "PID"), ("comment", "COMMENT")]);
+        listing.add_row(vec!["a\"b".into(), 12.into(), Field::Null]);
+
+        assert_eq!(
+            listing.to_json(&listing.rows[0]),
+            r#"{"id":"a\"b","pid":12,"comment":null}"#
+        );
+        assert_eq!(json_string("tab\there\u{1}"), r#""tab\there\u0001""#);
+    }
+
+    #[test]
+    fn it_escapes_tsv() {
+        assert_eq!(escape_tsv("a\tb\\c\nd"), "a\\tb\\\\c\\nd");
+    }
+}
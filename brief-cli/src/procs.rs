@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::process::{self, ExitCode};
+use std::time::Duration;
+
+use nix::sys::signal::kill as send_signal;
+use nix::unistd::{Pid, Uid, User};
+use psutil::host;
+use psutil::process::os::linux::ProcessExt;
+use psutil::process::{Process, Status};
+
+use crate::init::Service;
+use crate::jobs::parse_signal;
+use crate::output::{Field, Listing, Output};
+
+/// A process in the box
+#[derive(Clone, Debug)]
+pub struct Proc {
+    pub pid: i32,
+    pub ppid: i32,
+    pub user: String,
+    /// State letter as shown by `ps`
+    pub state: char,
+    /// Percentage of one CPU, over the process's lifetime on the first
+    /// sample and since the previous sample after that
+    pub cpu_percent: f32,
+    /// Resident memory in bytes
+    pub rss: u64,
+    pub cmdline: String,
+}
+
+/// PIDs of the processes that share the mount namespace of the service
+/// process `service_pid`, or `None` if it isn't running
+pub fn box_pids(service_pid: i32) -> Option<Vec<i32>> {
+    let mntid = fs::read_link(
+        Path::new("/proc")
+            .join(service_pid.to_string())
+            .join("ns/mnt"),
+    )
+    .ok()?;
+
+    let mut pids = vec![];
+    for entry in fs::read_dir("/proc").expect("Coult not read /proc") {
+        let Ok(entry) = entry else { continue };
+        let Some(pid) = entry.file_name().to_str().and_then(|x| x.parse().ok()) else {
+            continue;
+        };
+        if fs::read_link(entry.path().join("ns/mnt")).ok().as_ref() == Some(&mntid) {
+            pids.push(pid);
+        }
+    }
+    pids.sort();
+    Some(pids)
+}
+
+/// Whether `pid` shares the mount namespace of the service process
+/// `service_pid`, as the processes of `box_pids` do
+pub fn in_box(service_pid: i32, pid: i32) -> bool {
+    let mntid = |pid: i32| fs::read_link(Path::new("/proc").join(pid.to_string()).join("ns/mnt"));
+    matches!((mntid(service_pid), mntid(pid)), (Ok(a), Ok(b)) if a == b)
+}
+
+/// Inode number identifying namespace `ns` of `pid`, as in `lsns`
+pub fn namespace_id(pid: i32, ns: &str) -> Option<i64> {
+    let link = fs::read_link(Path::new("/proc").join(pid.to_string()).join("ns").join(ns)).ok()?;
+    link.to_str()?
+        .strip_prefix(ns)?
+        .strip_prefix(":[")?
+        .strip_suffix(']')?
+        .parse()
+        .ok()
+}
+
+/// How long `pid` has been running
+pub fn uptime(pid: i32) -> Option<Duration> {
+    let started = Process::new(pid as u32).ok()?.create_time();
+    host::uptime().ok()?.checked_sub(started)
+}
+
+/// Takes snapshots of the box's processes. The processes are kept between
+/// snapshots, so that CPU usage is measured over the time in between.
+#[derive(Default)]
+pub struct Sampler {
+    processes: HashMap<i32, Process>,
+}
+
+impl Sampler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sample(&mut self, service_pid: i32) -> Option<Vec<Proc>> {
+        let mut processes = HashMap::new();
+        let mut procs = vec![];
+        for pid in box_pids(service_pid)? {
+            let known = self.processes.remove(&pid).filter(|x| !x.is_replaced());
+            let (process, cpu_percent) = match known {
+                Some(mut process) => {
+                    let cpu_percent = process.cpu_percent().unwrap_or_default();
+                    (process, cpu_percent)
+                }
+                None => {
+                    // The process exited while we were looking at it
+                    let Ok(process) = Process::new(pid as u32) else {
+                        continue;
+                    };
+                    let cpu_percent = lifetime_cpu_percent(&process).unwrap_or_default();
+                    (process, cpu_percent)
+                }
+            };
+            if let Some(proc) = describe(&process, cpu_percent) {
+                procs.push(proc);
+                processes.insert(pid, process);
+            }
+        }
+        self.processes = processes;
+        Some(procs)
+    }
+}
+
+fn lifetime_cpu_percent(process: &Process) -> Option<f32> {
+    let busy = process.cpu_times().ok()?.busy();
+    let age = host::uptime().ok()?.checked_sub(process.create_time())?;
+    match age.is_zero() {
+        true => None,
+        false => Some(busy.as_secs_f32() / age.as_secs_f32() * 100.0),
+    }
+}
+
+fn describe(process: &Process, cpu_percent: f32) -> Option<Proc> {
+    let stat = process.procfs_stat().ok()?;
+    let user = process
+        .procfs_status()
+        .ok()
+        .map(|status| {
+            let uid = Uid::from_raw(status.uid[1]);
+            match User::from_uid(uid) {
+                Ok(Some(user)) => user.name,
+                _ => uid.to_string(),
+            }
+        })
+        .unwrap_or_default();
+    let cmdline = match process.cmdline().ok().flatten() {
+        Some(cmdline) if !cmdline.is_empty() => cmdline,
+        // Kernel threads and zombies have no command line
+        _ => format!("[{}]", stat.comm),
+    };
+
+    Some(Proc {
+        pid: process.pid() as i32,
+        ppid: stat.ppid.map(|x| x as i32).unwrap_or_default(),
+        user,
+        state: state_letter(stat.state),
+        cpu_percent,
+        rss: stat.rss.max(0) as u64,
+        cmdline,
+    })
+}
+
+fn state_letter(state: Status) -> char {
+    match state {
+        Status::Running => 'R',
+        Status::Sleeping => 'S',
+        Status::DiskSleep => 'D',
+        Status::Stopped => 'T',
+        Status::TracingStop => 't',
+        Status::Zombie => 'Z',
+        Status::Dead => 'X',
+        Status::WakeKill => 'K',
+        Status::Waking => 'W',
+        Status::Parked => 'P',
+        Status::Idle => 'I',
+        _ => '?',
+    }
+}
+
+/// List the box's processes, those of `user` whose command line contains
+/// `command` if given
+pub fn list(output: &Output, user: Option<&str>, command: Option<&str>) -> ExitCode {
+    let Some(procs) = Service::from_existing().and_then(|x| Sampler::new().sample(x.pid)) else {
+        return not_running();
+    };
+
+    let mut listing = Listing::new(&[
+        ("pid", "PID"),
+        ("ppid", "PPID"),
+        ("user", "USER"),
+        ("state", "S"),
+        ("cpu_percent", "%CPU"),
+        ("rss", "RSS"),
+        ("cmdline", "COMMAND"),
+    ]);
+    listing.truncate(&["cmdline"]);
+    for proc in procs {
+        if user.is_some_and(|x| x != proc.user)
+            || command.is_some_and(|x| !proc.cmdline.contains(x))
+        {
+            continue;
+        }
+        listing.add_row(vec![
+            proc.pid.into(),
+            proc.ppid.into(),
+            proc.user.into(),
+            proc.state.to_string().into(),
+            Field::Float(proc.cpu_percent.into()),
+            Field::Bytes(proc.rss),
+            proc.cmdline.into(),
+        ]);
+    }
+    listing.print(output)
+}
+
+/// Signal the box process `target`, given as a PID or as text that its
+/// command line contains, or with `all` every process in the box. Only
+/// processes in the box's mount namespace are signalled, and the service
+/// process only when asked for by PID.
+pub fn kill(target: Option<&str>, all: bool, signal: &str) -> ExitCode {
+    let Some(signal) = parse_signal(signal) else {
+        eprintln!("Unknown signal '{}'", signal);
+        return ExitCode::FAILURE;
+    };
+    let Some(service) = Service::from_existing() else {
+        return not_running();
+    };
+    let Some(procs) = Sampler::new().sample(service.pid) else {
+        return not_running();
+    };
+
+    let own_pid = process::id() as i32;
+    let targets: Vec<&Proc> = match target.map(|x| x.parse::<i32>()) {
+        Some(Ok(pid)) => {
+            let Some(proc) = procs.iter().find(|x| x.pid == pid) else {
+                eprintln!("Process {} is not in the box", pid);
+                return ExitCode::FAILURE;
+            };
+            vec![proc]
+        }
+        _ => procs
+            .iter()
+            .filter(|x| x.pid != service.pid && x.pid != own_pid)
+            .filter(|x| all || target.is_some_and(|target| x.cmdline.contains(target)))
+            .collect(),
+    };
+    if targets.is_empty() {
+        eprintln!(
+            "No process in the box matches '{}'",
+            target.unwrap_or_default()
+        );
+        return ExitCode::FAILURE;
+    }
+
+    let mut exit = ExitCode::SUCCESS;
+    for proc in targets {
+        match send_signal(Pid::from_raw(proc.pid), signal) {
+            Ok(()) => println!("Sent {} to {} ({})", signal, proc.pid, proc.cmdline),
+            Err(err) => {
+                eprintln!("Could not signal {}: {}", proc.pid, err);
+                exit = ExitCode::FAILURE;
+            }
+        }
+    }
+    exit
+}
+
+fn not_running() -> ExitCode {
+    eprintln!("nixbox not running");
+    ExitCode::FAILURE
+}
+
+/// Processes in tree order, children after their parent, with their depth
+/// in the tree. Processes whose parent is outside the box are roots.
+pub fn tree(procs: &[Proc]) -> Vec<(usize, &Proc)> {
+    let mut children: HashMap<i32, Vec<&Proc>> = HashMap::new();
+    let mut roots = vec![];
+    for proc in procs {
+        match procs.iter().any(|x| x.pid == proc.ppid) {
+            true => children.entry(proc.ppid).or_default().push(proc),
+            false => roots.push(proc),
+        }
+    }
+
+    let mut ordered = vec![];
+    let mut stack: Vec<(usize, &Proc)> = roots.into_iter().rev().map(|x| (0, x)).collect();
+    while let Some((depth, proc)) = stack.pop() {
+        ordered.push((depth, proc));
+        if let Some(children) = children.get(&proc.pid) {
+            stack.extend(children.iter().rev().map(|x| (depth + 1, *x)));
+        }
+    }
+    ordered
+}
+
+/// Size in bytes with a binary unit, eg. `12.5M`
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "K", "M", "G", "T"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{}{}", bytes, UNITS[0]),
+        _ => format!("{:.1}{}", size, UNITS[unit]),
+    }
+}
+
+/// Like `jobs::format_age`, but with the next smaller unit too, eg. `2h 5m`
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        3600..=86399 => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d {}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proc(pid: i32, ppid: i32) -> Proc {
+        Proc {
+            pid,
+            ppid,
+            user: String::new(),
+            state: 'S',
+            cpu_percent: 0.0,
+            rss: 0,
+            cmdline: String::new(),
+        }
+    }
+
+    #[test]
+    fn it_orders_processes_as_a_tree() {
+        let procs = vec![
+            proc(10, 1),
+            proc(11, 10),
+            proc(12, 99),
+            proc(13, 10),
+            proc(14, 11),
+        ];
+        let order: Vec<(usize, i32)> = tree(&procs).iter().map(|(d, x)| (*d, x.pid)).collect();
+
+        assert_eq!(order, vec![(0, 10), (1, 11), (2, 14), (1, 13), (0, 12)]);
+    }
+
+    #[test]
+    fn it_formats_sizes() {
+        assert_eq!(format_bytes(512), "512B");
+        assert_eq!(format_bytes(1536), "1.5K");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.0G");
+    }
+}
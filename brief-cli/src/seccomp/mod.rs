@@ -0,0 +1,558 @@
+mod syscalls;
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::thread;
+
+use nix::sys::socket::{
+    recvmsg, socketpair, AddressFamily, ControlMessageOwned, MsgFlags, SockFlag, SockType,
+};
+
+use crate::events::{Event, Reporter};
+use crate::util::KeyFile;
+
+/// Profiles that are always there. Box settings can add more as
+/// `[Seccomp Profile NAME]` groups.
+pub const BUILTIN: &[&str] = &["none", "default", "strict"];
+
+/// Administration of the machine, which has no business in the box
+const DEFAULT_DENY: &[&str] = &[
+    "acct",
+    "add_key",
+    "adjtimex",
+    "bpf",
+    "clock_adjtime",
+    "clock_settime",
+    "create_module",
+    "delete_module",
+    "finit_module",
+    "fsconfig",
+    "fsmount",
+    "fsopen",
+    "fspick",
+    "init_module",
+    "ioperm",
+    "iopl",
+    "kexec_file_load",
+    "kexec_load",
+    "keyctl",
+    "lookup_dcookie",
+    "mount",
+    "mount_setattr",
+    "move_mount",
+    "open_by_handle_at",
+    "open_tree",
+    "pivot_root",
+    "quotactl",
+    "reboot",
+    "request_key",
+    "settimeofday",
+    "swapoff",
+    "swapon",
+    "syslog",
+    "umount2",
+    "uselib",
+];
+
+/// Allowed only on processes in the box, which the supervisor checks
+const DEFAULT_CHECKED: &[&str] = &["ptrace", "process_vm_readv", "process_vm_writev"];
+
+/// On top of `DEFAULT_DENY`: namespaces, tracing and other ways to look
+/// into or get out of the box
+const STRICT_DENY: &[&str] = &[
+    "chroot",
+    "io_uring_enter",
+    "io_uring_register",
+    "io_uring_setup",
+    "kcmp",
+    "mknod",
+    "mknodat",
+    "name_to_handle_at",
+    "perf_event_open",
+    "personality",
+    "pidfd_getfd",
+    "process_vm_readv",
+    "process_vm_writev",
+    "ptrace",
+    "setns",
+    "unshare",
+    "userfaultfd",
+];
+
+/// What brief itself calls between installing a filter and executing the
+/// command, which an allow list can't take away
+const STARTUP: &[&str] = &[
+    "close",
+    "execve",
+    "exit_group",
+    "ioctl",
+    "sendmsg",
+    "setsid",
+    "write",
+];
+
+/// The profile for a session: `requested`, or else the `Profile` of the
+/// `[Seccomp]` settings. `None` when it doesn't filter.
+pub fn profile_for(settings: &KeyFile, requested: Option<&str>) -> Result<Option<Profile>, String> {
+    let name = requested
+        .or_else(|| settings.get("Seccomp", "Profile"))
+        .unwrap_or("none");
+    Profile::load(settings, name)
+}
+
+pub fn syscall_number(name: &str) -> Option<libc::c_long> {
+    syscalls::COMMON
+        .iter()
+        .chain(syscalls::ARCH)
+        .find(|(x, _)| *x == name)
+        .map(|(_, nr)| *nr)
+}
+
+pub fn syscall_name(nr: libc::c_long) -> Option<&'static str> {
+    syscalls::COMMON
+        .iter()
+        .chain(syscalls::ARCH)
+        .find(|(_, x)| *x == nr)
+        .map(|(name, _)| *name)
+}
+
+/// Which system calls the processes of a session may make. Denied calls fail
+/// with `EPERM` and are recorded as `syscall-blocked` events.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    deny: Vec<libc::c_long>,
+    /// Allowed when they target a process in the box
+    checked: Vec<libc::c_long>,
+    /// When set, everything else is denied
+    allow: Option<Vec<libc::c_long>>,
+}
+
+impl Profile {
+    /// The profile `name`, or `None` for `none`, which doesn't filter
+    pub fn load(settings: &KeyFile, name: &str) -> Result<Option<Self>, String> {
+        let numbers = |names: &[&str]| -> Vec<libc::c_long> {
+            // Some only exist on some architectures
+            names.iter().filter_map(|x| syscall_number(x)).collect()
+        };
+        let mut profile = Self {
+            name: name.to_string(),
+            ..Self::default()
+        };
+        match name {
+            "none" => return Ok(None),
+            "default" => {
+                profile.deny = numbers(DEFAULT_DENY);
+                profile.checked = numbers(DEFAULT_CHECKED);
+            }
+            "strict" => {
+                profile.deny = numbers(DEFAULT_DENY);
+                profile.deny.extend(numbers(STRICT_DENY));
+            }
+            _ => return Self::parse(settings, name).map(Some),
+        }
+        Ok(Some(profile))
+    }
+
+    /// A profile from the `[Seccomp Profile NAME]` group: the profile named by
+    /// `Base`, if any, with the syscalls of `Deny` denied too and, when
+    /// `Allow` is given, all but those denied
+    fn parse(settings: &KeyFile, name: &str) -> Result<Self, String> {
+        let group = format!("Seccomp Profile {}", name);
+        if settings.group(&group).is_none() {
+            return Err(format!(
+                "Unknown seccomp profile '{}': use {} or add a [{}] group",
+                name,
+                BUILTIN.join(", "),
+                group
+            ));
+        }
+        let numbers = |key: &str| -> Result<Vec<libc::c_long>, String> {
+            settings
+                .get_list(&group, key)
+                .iter()
+                .map(|x| {
+                    syscall_number(x)
+                        .ok_or_else(|| format!("[{}] {}: unknown system call '{}'", group, key, x))
+                })
+                .collect()
+        };
+
+        let mut profile = match settings.get(&group, "Base") {
+            Some(base) if base == name => {
+                return Err(format!("[{}] Base: a profile can't extend itself", group))
+            }
+            Some(base) => Self::load(settings, base)?.unwrap_or_default(),
+            None => Self::default(),
+        };
+        profile.name = name.to_string();
+        profile.deny.extend(numbers("Deny")?);
+        if settings.get(&group, "Allow").is_some() {
+            let mut allow = numbers("Allow")?;
+            allow.extend(STARTUP.iter().filter_map(|x| syscall_number(x)));
+            profile.allow = Some(allow);
+        }
+        Ok(profile)
+    }
+
+    /// Whether the profile lets through nothing that `other` denies, or
+    /// allows only for processes in the box
+    pub fn covers(&self, other: &Profile) -> bool {
+        let denies = |nr: &libc::c_long| {
+            self.deny.contains(nr) || self.allow.as_ref().is_some_and(|x| !x.contains(nr))
+        };
+        other.deny.iter().all(denies)
+            && other
+                .checked
+                .iter()
+                .all(|nr| denies(nr) || self.checked.contains(nr))
+            && other.allow.as_ref().is_none_or(|allowed| {
+                self.allow
+                    .as_ref()
+                    .is_some_and(|x| x.iter().all(|nr| allowed.contains(nr) || denies(nr)))
+            })
+    }
+
+    /// The BPF program of the profile, which has denied calls return
+    /// `deny` as action
+    fn program(&self, deny: u32) -> Vec<libc::sock_filter> {
+        let mut program = vec![
+            load(ARCH_OFFSET),
+            jump_eq(AUDIT_ARCH, 1, 0),
+            ret(EPERM),
+            load(NR_OFFSET),
+        ];
+        if cfg!(target_arch = "x86_64") {
+            // The x32 ABI, whose calls have other numbers
+            program.push(jump(libc::BPF_JGE, X32_SYSCALL_BIT, 0, 1));
+            program.push(ret(EPERM));
+        }
+        for nr in &self.deny {
+            program.push(jump_eq(*nr as u32, 0, 1));
+            program.push(ret(deny));
+        }
+        for nr in &self.checked {
+            if *nr == libc::SYS_ptrace {
+                // Only attaching needs checking, the other requests are about
+                // processes we already trace
+                program.push(jump_eq(*nr as u32, 0, 5));
+                program.push(load(ARGS_OFFSET));
+                program.push(jump_eq(libc::PTRACE_ATTACH, 2, 0));
+                program.push(jump_eq(libc::PTRACE_SEIZE, 1, 0));
+                program.push(ret(libc::SECCOMP_RET_ALLOW));
+                program.push(ret(deny));
+                continue;
+            }
+            program.push(jump_eq(*nr as u32, 0, 1));
+            program.push(ret(deny));
+        }
+        match &self.allow {
+            Some(allow) => {
+                for nr in allow {
+                    program.push(jump_eq(*nr as u32, 0, 1));
+                    program.push(ret(libc::SECCOMP_RET_ALLOW));
+                }
+                program.push(ret(deny));
+            }
+            None => program.push(ret(libc::SECCOMP_RET_ALLOW)),
+        }
+        program
+    }
+
+    /// Have `command` set `no_new_privs` and install the filter before it
+    /// executes, with a thread of ours answering for the calls it denies and
+    /// telling `reporter` about them
+    pub fn apply_to(
+        &self,
+        command: &mut Command,
+        session: &str,
+        reporter: Option<Reporter>,
+    ) -> io::Result<()> {
+        let (ours, theirs) = socketpair(
+            AddressFamily::Unix,
+            SockType::Datagram,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )?;
+        // Built here, as the child shouldn't allocate
+        let supervised = self.program(SECCOMP_RET_USER_NOTIF);
+        let fallback = self.program(EPERM);
+        unsafe {
+            command.pre_exec(move || install(&supervised, &fallback, theirs.as_raw_fd()));
+        }
+
+        let supervisor = Supervisor {
+            profile: self.clone(),
+            session: session.to_string(),
+            reporter,
+            seen: HashSet::new(),
+        };
+        thread::spawn(move || {
+            if let Some(listener) = receive_fd(&ours) {
+                supervisor.serve(listener);
+            }
+        });
+        Ok(())
+    }
+}
+
+const ARCH_OFFSET: u32 = 4;
+const NR_OFFSET: u32 = 0;
+/// Of the lower half of the first argument
+const ARGS_OFFSET: u32 = 16;
+const EPERM: u32 = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+const SECCOMP_RET_USER_NOTIF: u32 = 0x7fc0_0000;
+const SECCOMP_IOCTL_NOTIF_RECV: libc::c_ulong = 0xc050_2100;
+const SECCOMP_IOCTL_NOTIF_SEND: libc::c_ulong = 0xc018_2101;
+
+fn load(offset: u32) -> libc::sock_filter {
+    statement((libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16, offset)
+}
+
+fn ret(action: u32) -> libc::sock_filter {
+    statement((libc::BPF_RET | libc::BPF_K) as u16, action)
+}
+
+fn jump_eq(k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    jump(libc::BPF_JEQ, k, jt, jf)
+}
+
+fn jump(op: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: (libc::BPF_JMP | op | libc::BPF_K) as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+fn statement(code: u16, k: u32) -> libc::sock_filter {
+    libc::sock_filter {
+        code,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+/// Install the filter in the current process and send its listener over
+/// `socket`. Kernels without listeners get `fallback`, which denies without
+/// telling anyone.
+fn install(
+    supervised: &[libc::sock_filter],
+    fallback: &[libc::sock_filter],
+    socket: RawFd,
+) -> io::Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let seccomp = |program: &[libc::sock_filter], flags: libc::c_ulong| {
+        let program = libc::sock_fprog {
+            len: program.len() as u16,
+            filter: program.as_ptr() as *mut libc::sock_filter,
+        };
+        unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                flags,
+                &program as *const libc::sock_fprog,
+            )
+        }
+    };
+
+    let listener = seccomp(supervised, libc::SECCOMP_FILTER_FLAG_NEW_LISTENER);
+    if listener < 0 {
+        if seccomp(fallback, 0) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        return Ok(());
+    }
+    send_fd(socket, listener as RawFd);
+    unsafe { libc::close(listener as RawFd) };
+    Ok(())
+}
+
+/// Send `fd` as the only thing in a message, without allocating
+fn send_fd(socket: RawFd, fd: RawFd) {
+    #[repr(C)]
+    union Control {
+        buf: [u8; 64],
+        _align: libc::cmsghdr,
+    }
+    let mut control = Control { buf: [0; 64] };
+    let mut byte = 0u8;
+    let mut iov = libc::iovec {
+        iov_base: &mut byte as *mut u8 as *mut libc::c_void,
+        iov_len: 1,
+    };
+    unsafe {
+        let mut message: libc::msghdr = mem::zeroed();
+        message.msg_iov = &mut iov;
+        message.msg_iovlen = 1;
+        message.msg_control = control.buf.as_mut_ptr() as *mut libc::c_void;
+        message.msg_controllen = libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) as usize;
+        let header = libc::CMSG_FIRSTHDR(&message);
+        (*header).cmsg_level = libc::SOL_SOCKET;
+        (*header).cmsg_type = libc::SCM_RIGHTS;
+        (*header).cmsg_len = libc::CMSG_LEN(mem::size_of::<RawFd>() as u32) as usize;
+        *(libc::CMSG_DATA(header) as *mut RawFd) = fd;
+        libc::sendmsg(socket, &message, 0);
+    }
+}
+
+fn receive_fd(socket: &OwnedFd) -> Option<OwnedFd> {
+    let mut byte = [0u8; 1];
+    let mut iov = [io::IoSliceMut::new(&mut byte)];
+    let mut space = nix::cmsg_space!(RawFd);
+    let message = recvmsg::<()>(
+        socket.as_raw_fd(),
+        &mut iov,
+        Some(&mut space),
+        MsgFlags::empty(),
+    )
+    .ok()?;
+    let fd = message.cmsgs().ok()?.find_map(|x| match x {
+        ControlMessageOwned::ScmRights(fds) => fds.first().copied(),
+        _ => None,
+    })?;
+    Some(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Answers the calls that a profile denies or checks
+struct Supervisor {
+    profile: Profile,
+    session: String,
+    reporter: Option<Reporter>,
+    /// (pid, syscall) already recorded, to not flood the journal with retries
+    seen: HashSet<(u32, i32)>,
+}
+
+impl Supervisor {
+    /// Serve until every process using the filter is gone
+    fn serve(mut self, listener: OwnedFd) {
+        loop {
+            let mut request: libc::seccomp_notif = unsafe { mem::zeroed() };
+            let received = unsafe {
+                libc::ioctl(
+                    listener.as_raw_fd(),
+                    SECCOMP_IOCTL_NOTIF_RECV as _,
+                    &mut request,
+                )
+            };
+            if received < 0 {
+                match io::Error::last_os_error().raw_os_error() {
+                    // Interrupted, or the process died before we got to it
+                    Some(libc::EINTR) | Some(libc::ENOENT) => continue,
+                    _ => return,
+                }
+            }
+
+            let nr = request.data.nr as libc::c_long;
+            let allowed = self.profile.checked.contains(&nr) && targets_box(&request);
+            let mut response: libc::seccomp_notif_resp = unsafe { mem::zeroed() };
+            response.id = request.id;
+            match allowed {
+                true => response.flags = libc::SECCOMP_USER_NOTIF_FLAG_CONTINUE as u32,
+                false => response.error = -libc::EPERM,
+            }
+            unsafe {
+                libc::ioctl(
+                    listener.as_raw_fd(),
+                    SECCOMP_IOCTL_NOTIF_SEND as _,
+                    &mut response,
+                )
+            };
+            if !allowed && self.seen.insert((request.pid, request.data.nr)) {
+                self.report(&request);
+            }
+        }
+    }
+
+    /// Record a denied call. This only writes to the journal or a pipe, as
+    /// forking from here could deadlock the session.
+    fn report(&self, request: &libc::seccomp_notif) {
+        let Some(reporter) = &self.reporter else {
+            return;
+        };
+        let name = syscall_name(request.data.nr as libc::c_long)
+            .map(String::from)
+            .unwrap_or_else(|| format!("syscall {}", request.data.nr));
+        let command = fs::read_to_string(format!("/proc/{}/comm", request.pid))
+            .map(|x| x.trim_end().to_string())
+            .ok();
+        reporter.report(
+            Event::new("syscall-blocked")
+                .with("syscall", name)
+                .with("pid", request.pid)
+                .with("command", command)
+                .with("profile", self.profile.name.as_str())
+                .with("session", self.session.as_str()),
+        );
+    }
+}
+
+/// Whether a checked call is about a process in the box, ie. one in our
+/// mount namespace
+fn targets_box(request: &libc::seccomp_notif) -> bool {
+    let args = request.data.args;
+    let pid = match syscall_name(request.data.nr as libc::c_long) {
+        Some("ptrace") => args[1],
+        _ => args[0],
+    } as i32;
+    let ns = |pid: &str| fs::read_link(format!("/proc/{}/ns/mnt", pid)).ok();
+    pid > 0 && ns(&pid.to_string()).is_some() && ns(&pid.to_string()) == ns("self")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_loads_profiles() {
+        let settings = KeyFile::parse(
+            "[Seccomp Profile build]\nBase=default\nDeny=unshare;setns\n\
+             [Seccomp Profile tiny]\nAllow=read;exit\n\
+             [Seccomp Profile typo]\nDeny=mountt\n",
+        );
+
+        assert_eq!(Profile::load(&settings, "none"), Ok(None));
+        let default = Profile::load(&settings, "default").unwrap().unwrap();
+        assert!(default.deny.contains(&libc::SYS_mount));
+        assert!(default.checked.contains(&libc::SYS_ptrace));
+
+        let build = Profile::load(&settings, "build").unwrap().unwrap();
+        assert!(build.deny.contains(&libc::SYS_keyctl));
+        assert!(build.deny.contains(&libc::SYS_unshare));
+        assert_eq!(build.allow, None);
+
+        let tiny = Profile::load(&settings, "tiny").unwrap().unwrap();
+        let allow = tiny.allow.unwrap();
+        assert!(allow.contains(&libc::SYS_read) && allow.contains(&libc::SYS_execve));
+
+        assert!(Profile::load(&settings, "typo").is_err());
+        assert!(Profile::load(&settings, "missing").is_err());
+    }
+
+    #[test]
+    fn it_compares_profiles() {
+        let settings = KeyFile::parse("[Seccomp Profile tiny]\nAllow=read;exit\n");
+        let load = |name| Profile::load(&settings, name).unwrap().unwrap();
+
+        assert!(load("strict").covers(&load("default")));
+        assert!(!load("default").covers(&load("strict")));
+        assert!(load("tiny").covers(&load("strict")));
+        assert!(!load("strict").covers(&load("tiny")));
+    }
+}
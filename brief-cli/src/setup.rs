@@ -138,7 +138,7 @@ fn bind_nix_profile(chroot_dir: &Path, nix_dir: &Path, nixbox_root: &Path) {
     // bind /etc
     create_dir(chroot_dir.join("etc")).expect("could not create etc dir");
     for file_name in ["resolv.conf", "passwd", "group", "group-", "fonts"] {
-        bind(&Path::new("/etc").join(file_name), chroot_dir.join("etc"));
+        bind(Path::new("/etc").join(file_name), chroot_dir.join("etc"));
     }
     copy_certs(chroot_dir);
 
@@ -146,7 +146,7 @@ fn bind_nix_profile(chroot_dir: &Path, nix_dir: &Path, nixbox_root: &Path) {
     create_dir_all(chroot_dir.join("usr/share")).expect("could not create usr/share dir");
     for file_name in ["fonts", "fontconfig", "icons"] {
         bind(
-            &Path::new("/usr/share").join(file_name),
+            Path::new("/usr/share").join(file_name),
             chroot_dir.join("usr/share"),
         );
     }
@@ -1,45 +1,283 @@
-use std::fs::{self, File};
-use std::io::Read;
+use std::fs;
 use std::path::Path;
 use std::process::ExitCode;
 
+use crate::config::{Config, BOX_NAME};
 use crate::init::Service;
+use crate::limits;
+use crate::output::{Field, Format, Listing, Output};
+use crate::procs::{self, box_pids, namespace_id, Sampler};
 
-pub fn status() -> ExitCode {
+pub fn status(output: &Output) -> ExitCode {
     let Some(service) = Service::from_existing() else {
-        return not_running();
+        return not_running(output);
     };
+    let Some(procs) = Sampler::new().sample(service.pid) else {
+        return not_running(output);
+    };
+    let uptime = procs::uptime(service.pid);
+
+    let namespaces: Vec<(&str, Option<i64>)> = NAMESPACES
+        .iter()
+        .map(|ns| (*ns, namespace_id(service.pid, ns)))
+        .collect();
+    let mut listing = Listing::new(&[
+        ("box", ""),
+        ("pid", "PID"),
+        ("ppid", ""),
+        ("depth", ""),
+        ("user", "USER"),
+        ("state", "S"),
+        ("cpu_percent", "%CPU"),
+        ("rss", "RSS"),
+        ("cmdline", "COMMAND"),
+        ("service_uptime", ""),
+        ("user_ns", ""),
+        ("mnt_ns", ""),
+        ("uts_ns", ""),
+    ]);
+    // Sorted rows aren't in tree order, so only indent unsorted tables
+    let indent = output.format == Format::Table && output.sort.is_none();
+    for (depth, proc) in procs::tree(&procs) {
+        let cmdline = match depth {
+            0 => proc.cmdline.clone(),
+            _ if indent => format!("{}\\_ {}", "  ".repeat(depth - 1), proc.cmdline),
+            _ => proc.cmdline.clone(),
+        };
+        let mut row = vec![
+            BOX_NAME.into(),
+            proc.pid.into(),
+            proc.ppid.into(),
+            (depth as i64).into(),
+            proc.user.clone().into(),
+            proc.state.to_string().into(),
+            Field::Float(proc.cpu_percent.into()),
+            Field::Bytes(proc.rss),
+            cmdline.into(),
+            uptime.map(|x| x.as_secs() as i64).into(),
+        ];
+        row.extend(namespaces.iter().map(|(_, id)| Field::from(*id)));
+        listing.add_row(row);
+    }
+    listing.truncate(&["cmdline"]);
+
+    let table = output.format == Format::Table && !output.no_headers;
+    if table {
+        match uptime {
+            Some(uptime) => println!(
+                "{} running (PID: {}, up {})",
+                BOX_NAME,
+                service.pid,
+                procs::format_duration(uptime)
+            ),
+            None => println!("{} running (PID: {})", BOX_NAME, service.pid),
+        }
+        let ids: Vec<String> = namespaces
+            .iter()
+            .map(|(ns, id)| match id {
+                Some(id) => format!("{} {}", ns, id),
+                None => format!("{} ?", ns),
+            })
+            .collect();
+        println!("Namespaces: {}", ids.join(", "));
+        if let Some(limits) = limits::effective(service.pid) {
+            let limits: Vec<String> = limits
+                .iter()
+                .map(|(name, value)| format!("{} {}", name, value))
+                .collect();
+            println!("Limits: {}", limits.join(", "));
+        }
+        if let Some(config) = Config::new(true) {
+            match config
+                .current_system
+                .as_ref()
+                .and_then(|x| fs::read_link(x).ok())
+            {
+                Some(system) => println!("System: {}", system.display()),
+                None => println!("System: not installed"),
+            }
+            println!("Nix store: {}", config.nix_home.display());
+        }
+        println!();
+    }
 
-    let mnt = Path::new("/proc")
+    let exit = listing.print(output);
+    if table {
+        let cpu: f32 = procs.iter().map(|x| x.cpu_percent).sum();
+        let rss: u64 = procs.iter().map(|x| x.rss).sum();
+        println!(
+            "\n{} processes, {:.1}% CPU, {} RSS",
+            procs.len(),
+            cpu,
+            procs::format_bytes(rss)
+        );
+    }
+    exit
+}
+
+/// What is mounted where in the box, from the service's mountinfo
+pub fn mounts(output: &Output) -> ExitCode {
+    let Some(service) = Service::from_existing() else {
+        return not_running(output);
+    };
+    let path = Path::new("/proc")
         .join(service.pid.to_string())
-        .join("ns/mnt");
-    let Ok(mntid) = fs::read_link(mnt) else {
-        return not_running();
+        .join("mountinfo");
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("Could not read '{}': {}", path.display(), err);
+            return ExitCode::FAILURE;
+        }
     };
 
-    println!("nixbox running (PID: {})", service.pid);
-    println!("\nPID\t\tCOMMAND");
-    for entry in fs::read_dir("/proc").expect("Coult not read /proc") {
-        let Ok(entry) = entry else { continue };
-        let Ok(entry_mntid) = fs::read_link(entry.path().join("ns/mnt")) else {
-            continue;
-        };
-        if entry_mntid == mntid {
-            let mut cmdline = String::new();
-            File::open(entry.path().join("cmdline"))
-                .and_then(|mut file| file.read_to_string(&mut cmdline))
-                .expect("/proc/_/cmdline count not be read");
+    let mut listing = Listing::new(&[
+        ("id", ""),
+        ("parent_id", ""),
+        ("target", "TARGET"),
+        ("source", "SOURCE"),
+        ("root", "ROOT"),
+        ("fstype", "TYPE"),
+        ("options", "OPTIONS"),
+        ("super_options", ""),
+    ]);
+    for mount in parse_mountinfo(&text) {
+        listing.add_row(vec![
+            mount.id.into(),
+            mount.parent_id.into(),
+            mount.target.into(),
+            mount.source.into(),
+            mount.root.into(),
+            mount.fstype.into(),
+            mount.options.into(),
+            mount.super_options.into(),
+        ]);
+    }
+    listing.truncate(&["source", "root", "options"]);
+    listing.print(output)
+}
+
+/// A line of `/proc/<pid>/mountinfo`, see proc(5)
+#[derive(Debug, PartialEq)]
+pub struct Mount {
+    pub id: u32,
+    pub parent_id: u32,
+    /// The directory of the source file system that is mounted, `/` unless
+    /// it's a bind mount of a subdirectory
+    pub root: String,
+    pub target: String,
+    pub options: String,
+    pub fstype: String,
+    pub source: String,
+    pub super_options: String,
+}
+
+pub fn parse_mountinfo(text: &str) -> Vec<Mount> {
+    text.lines().filter_map(parse_mount).collect()
+}
 
-            cmdline = cmdline.replace('\0', " ");
+fn parse_mount(line: &str) -> Option<Mount> {
+    let mut fields = line.split(' ');
+    let id = fields.next()?.parse().ok()?;
+    let parent_id = fields.next()?.parse().ok()?;
+    let _device = fields.next()?;
+    let root = unescape(fields.next()?);
+    let target = unescape(fields.next()?);
+    let options = fields.next()?.to_string();
+    // Optional fields like `shared:1` end with a lone `-`
+    fields.find(|x| *x == "-")?;
+    let fstype = fields.next()?.to_string();
+    let source = unescape(fields.next()?);
+    let super_options = fields.next()?.to_string();
 
-            println!("{}\t\t{}", entry.file_name().to_str().unwrap(), cmdline,);
+    Some(Mount {
+        id,
+        parent_id,
+        root,
+        target,
+        options,
+        fstype,
+        source,
+        super_options,
+    })
+}
+
+/// Undo the octal escapes of spaces, tabs, newlines and backslashes
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut unescaped = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let code = bytes
+            .get(i + 1..i + 4)
+            .filter(|_| bytes[i] == b'\\')
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| u8::from_str_radix(x, 8).ok());
+        match code {
+            Some(code) => {
+                unescaped.push(code);
+                i += 4;
+            }
+            None => {
+                unescaped.push(bytes[i]);
+                i += 1;
+            }
         }
     }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+/// The namespaces the box doesn't share with the host
+const NAMESPACES: &[&str] = &["user", "mnt", "uts"];
 
-    ExitCode::SUCCESS
+/// PIDs and command lines of the processes that share the mount namespace of
+/// the service process `service_pid`, or `None` if it isn't running.
+pub fn box_processes(service_pid: i32) -> Option<Vec<(i32, String)>> {
+    let mut processes = vec![];
+    for pid in box_pids(service_pid)? {
+        let path = Path::new("/proc").join(pid.to_string()).join("cmdline");
+        // The process exited while we were looking at it
+        let Ok(cmdline) = fs::read_to_string(path) else {
+            continue;
+        };
+        processes.push((pid, cmdline.replace('\0', " ")));
+    }
+    Some(processes)
 }
 
-fn not_running() -> ExitCode {
-    println!("nixbox not running");
+fn not_running(output: &Output) -> ExitCode {
+    match output.format {
+        Format::Table => println!("nixbox not running"),
+        _ => eprintln!("nixbox not running"),
+    }
     ExitCode::FAILURE
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_mountinfo() {
+        let text = "\
+22 1 0:21 / / rw,relatime shared:1 - tmpfs tmpfs rw,size=4k
+36 22 98:0 /home/a\\040b /nix rw,noatime master:1 - ext4 /dev/sda1 rw,errors=continue
+";
+        let mounts = parse_mountinfo(text);
+
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(
+            mounts[1],
+            Mount {
+                id: 36,
+                parent_id: 22,
+                root: String::from("/home/a b"),
+                target: String::from("/nix"),
+                options: String::from("rw,noatime"),
+                fstype: String::from("ext4"),
+                source: String::from("/dev/sda1"),
+                super_options: String::from("rw,errors=continue"),
+            }
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::PathBuf;
+use std::process::{Command, ExitCode};
+
+use crate::app::host_dbus_services_dir;
+use crate::config::{host_config_home, Config, BOX_NAME};
+use crate::dbus;
+
+const MARKER: &str = "# Generated by brief. Do not edit.";
+
+/// Template unit, instantiated once per box
+const TEMPLATE: &str = "brief@.service";
+
+/// The unit of this box, eg. `brief@nixbox.service`
+pub fn unit_name() -> String {
+    format!("brief@{}.service", BOX_NAME)
+}
+
+fn unit_path() -> PathBuf {
+    host_config_home().join("systemd/user").join(TEMPLATE)
+}
+
+fn dbus_service_path() -> PathBuf {
+    host_dbus_services_dir().join(format!("{}.service", dbus::NAME))
+}
+
+/// Whether the service is managed by the systemd user manager, in which case
+/// clients start it through `systemctl --user`
+pub fn is_installed() -> bool {
+    unit_path().is_file()
+}
+
+/// Write the user unit for the service, and a D-Bus service file so that
+/// calls to `pink.wah.Brief1` start it. With `enable`, the box is also
+/// started now and on login.
+pub fn install(config: &Config, enable: bool) -> ExitCode {
+    if let Err(err) = write_unit(config) {
+        eprintln!("Could not write '{}': {}", unit_path().display(), err);
+        return ExitCode::FAILURE;
+    }
+    if let Err(err) = write_dbus_service() {
+        eprintln!(
+            "Could not write '{}': {}",
+            dbus_service_path().display(),
+            err
+        );
+        return ExitCode::FAILURE;
+    }
+    println!("Installed {}", unit_path().display());
+
+    if !systemctl(&["daemon-reload"]) {
+        return ExitCode::FAILURE;
+    }
+    if enable && !systemctl(&["enable", "--now", &unit_name()]) {
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+/// Disable and remove what `install` wrote
+pub fn uninstall() -> ExitCode {
+    if !is_installed() {
+        eprintln!("{} is not installed", unit_name());
+        return ExitCode::FAILURE;
+    }
+    systemctl(&["disable", "--now", &unit_name()]);
+
+    for path in [unit_path(), dbus_service_path()] {
+        if let Err(err) = fs::remove_file(&path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                eprintln!("Could not remove '{}': {}", path.display(), err);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    match systemctl(&["daemon-reload"]) {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    }
+}
+
+fn write_unit(config: &Config) -> io::Result<()> {
+    let path = unit_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let executable = quote_arg(&config.nixbox_executable().to_string_lossy());
+
+    let mut file = File::create(&path)?;
+    writeln!(file, "{}", MARKER)?;
+    writeln!(file, "[Unit]")?;
+    writeln!(file, "Description=brief box %i")?;
+    writeln!(file)?;
+    writeln!(file, "[Service]")?;
+    writeln!(file, "Type=notify")?;
+    writeln!(file, "ExecStart={} init", executable)?;
+    // `init` exits with 1 when the box is stopped through `brief`
+    writeln!(file, "SuccessExitStatus=1")?;
+    writeln!(file, "Restart=on-abnormal")?;
+    writeln!(file)?;
+    writeln!(file, "[Install]")?;
+    writeln!(file, "WantedBy=default.target")
+}
+
+fn write_dbus_service() -> io::Result<()> {
+    let path = dbus_service_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = File::create(&path)?;
+    writeln!(file, "{}", MARKER)?;
+    writeln!(file, "[D-BUS Service]")?;
+    writeln!(file, "Name={}", dbus::NAME)?;
+    // The bus only asks systemd to start the unit
+    writeln!(file, "Exec=/bin/false")?;
+    writeln!(file, "SystemdService={}", unit_name())
+}
+
+/// Quote an `ExecStart=` argument
+fn quote_arg(arg: &str) -> String {
+    let mut quoted = String::new();
+    for c in arg.chars() {
+        match c {
+            '\\' | '"' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '$' => quoted.push_str("$$"),
+            '%' => quoted.push_str("%%"),
+            _ => quoted.push(c),
+        }
+    }
+    match arg.contains(|c: char| c.is_whitespace() || c == '\'') || quoted != arg {
+        true => format!("\"{}\"", quoted),
+        false => quoted,
+    }
+}
+
+/// Run `systemctl --user` with `args`, reporting failures
+pub fn systemctl(args: &[&str]) -> bool {
+    match Command::new("systemctl").arg("--user").args(args).status() {
+        Ok(status) if status.success() => true,
+        Ok(status) => {
+            eprintln!("systemctl --user {} failed: {}", args.join(" "), status);
+            false
+        }
+        Err(err) => {
+            eprintln!("Could not run systemctl: {}", err);
+            false
+        }
+    }
+}
+
+/// Tell the service manager about our state, eg. `READY=1`, when it started
+/// us with `Type=notify`
+pub fn notify(state: &str) {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let path = path.to_string_lossy().into_owned();
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+        None => SocketAddr::from_pathname(&path),
+    };
+    let result =
+        addr.and_then(|addr| UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr));
+    if let Err(err) = result {
+        eprintln!("Could not notify the service manager: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_quotes_exec_args() {
+        assert_eq!(quote_arg("/usr/bin/brief"), "/usr/bin/brief");
+        assert_eq!(quote_arg("/home/a b/100%/$x"), "\"/home/a b/100%%/$$x\"");
+    }
+}
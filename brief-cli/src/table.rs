@@ -1,9 +1,22 @@
 use std::cmp::max;
+use std::env;
+use std::io::{self, IsTerminal};
+
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Space between columns
+const GAP: usize = 2;
+
+/// Truncated columns keep at least this many characters
+const MIN_WIDTH: usize = 8;
 
 #[derive(Default)]
 pub struct Table {
     headers: Vec<String>,
     rows: Vec<Vec<String>>,
+    /// Columns that are shortened to fit the terminal
+    truncate: Vec<usize>,
+    no_headers: bool,
 }
 
 impl Table {
@@ -19,35 +32,196 @@ impl Table {
         self.rows.push(row);
     }
 
+    /// Shorten column `col` with an ellipsis when the table is wider than
+    /// the terminal
+    pub fn truncate(&mut self, col: usize) {
+        self.truncate.push(col);
+    }
+
+    pub fn hide_headers(&mut self) {
+        self.no_headers = true;
+    }
+
     pub fn print(&self) {
-        let colsizes: Vec<usize> = self
-            .headers
+        let colour = use_colour();
+        for line in self.render(terminal_width()) {
+            match line {
+                (true, line) if colour => println!("\x1b[1m{}\x1b[0m", line),
+                (_, line) => println!("{}", line),
+            }
+        }
+    }
+
+    /// Lines of the table, fitted to `max_width` if given, and whether each
+    /// is the header line
+    fn render(&self, max_width: Option<usize>) -> Vec<(bool, String)> {
+        let clean = |cell: &str| cell.replace(['\t', '\n', '\r'], " ");
+        let headers: Vec<String> = self.headers.iter().map(|x| clean(x)).collect();
+        let rows: Vec<Vec<String>> = self
+            .rows
             .iter()
-            .enumerate()
-            .map(|(col, head)| {
-                max(
-                    self.rows
-                        .iter()
-                        .map(|row| row.get(col).map(|cell| cell.len()).unwrap_or(0))
-                        .max()
-                        .unwrap(),
-                    head.len(),
-                )
+            .map(|row| row.iter().map(|x| clean(x)).collect())
+            .collect();
+
+        let mut widths: Vec<usize> = (0..headers.len())
+            .map(|col| {
+                rows.iter()
+                    .filter_map(|row| row.get(col))
+                    .map(|cell| cell.width())
+                    .fold(
+                        match self.no_headers {
+                            true => 0,
+                            false => headers[col].width(),
+                        },
+                        max,
+                    )
             })
             .collect();
 
-        // Print head
-        for (i, head) in self.headers.iter().enumerate() {
-            print!("{:len$}\t", head, len = colsizes.get(i).unwrap_or(&0));
+        if let Some(max_width) = max_width {
+            let total = |widths: &[usize]| {
+                widths.iter().sum::<usize>() + GAP * widths.len().saturating_sub(1)
+            };
+            while total(&widths) > max_width {
+                // Take from the widest column that may still shrink
+                let widest = self
+                    .truncate
+                    .iter()
+                    .copied()
+                    .filter(|col| widths.get(*col).map(|x| *x > MIN_WIDTH) == Some(true))
+                    .max_by_key(|col| widths[*col]);
+                match widest {
+                    Some(col) => widths[col] -= 1,
+                    None => break,
+                }
+            }
         }
-        println!();
 
-        // Print rows
-        for row in &self.rows {
-            for (i, cell) in row.iter().enumerate() {
-                print!("{:len$}\t", cell, len = colsizes.get(i).unwrap_or(&0));
-            }
-            println!();
+        let mut lines = vec![];
+        if !self.no_headers {
+            lines.push((true, format_row(&headers, &widths)));
+        }
+        for row in &rows {
+            lines.push((false, format_row(row, &widths)));
+        }
+        lines
+    }
+}
+
+fn format_row(row: &[String], widths: &[usize]) -> String {
+    let mut line = String::new();
+    for (col, width) in widths.iter().enumerate() {
+        let cell = truncate(row.get(col).map(String::as_str).unwrap_or_default(), *width);
+        if col > 0 {
+            line.push_str(&" ".repeat(GAP));
+        }
+        line.push_str(&cell);
+        line.push_str(&" ".repeat(width.saturating_sub(cell.width())));
+    }
+    line.trim_end().to_string()
+}
+
+/// Shorten `text` to `width` columns, ending it with an ellipsis if it had to
+/// be cut
+pub fn truncate(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+    let mut truncated = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let char_width = c.width().unwrap_or(0);
+        if used + char_width + 1 > width {
+            break;
         }
+        truncated.push(c);
+        used += char_width;
+    }
+    truncated.push('…');
+    truncated
+}
+
+/// Width of the terminal on stdout, `None` when output goes elsewhere and
+/// shouldn't be cut
+fn terminal_width() -> Option<usize> {
+    if !io::stdout().is_terminal() {
+        return None;
+    }
+    match terminal_size() {
+        Some((columns, _)) => Some(columns),
+        None => env::var("COLUMNS").ok().and_then(|x| x.parse().ok()),
+    }
+}
+
+/// Columns and rows of the terminal on stdout
+pub fn terminal_size() -> Option<(usize, usize)> {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    match unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } {
+        0 if size.ws_col > 0 && size.ws_row > 0 => Some((size.ws_col.into(), size.ws_row.into())),
+        _ => None,
+    }
+}
+
+/// Colour only goes to terminals, and not when NO_COLOR is set
+/// (https://no-color.org)
+fn use_colour() -> bool {
+    io::stdout().is_terminal() && env::var_os("NO_COLOR").map(|x| x.is_empty()) != Some(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> Table {
+        let mut table = Table::new();
+        table.add_header(String::from("ID"));
+        table.add_header(String::from("COMMENT"));
+        table.add_row(vec![
+            String::from("blåbær"),
+            String::from("A rather long comment"),
+        ]);
+        table.add_row(vec![String::from("日本"), String::from("Short")]);
+        table
+    }
+
+    fn lines(table: &Table, max_width: Option<usize>) -> Vec<String> {
+        table.render(max_width).into_iter().map(|x| x.1).collect()
+    }
+
+    #[test]
+    fn it_pads_by_display_width() {
+        assert_eq!(
+            lines(&table(), None),
+            vec![
+                "ID      COMMENT",
+                "blåbær  A rather long comment",
+                "日本    Short",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_truncates_to_the_terminal() {
+        let mut table = table();
+        table.truncate(1);
+        table.hide_headers();
+
+        assert_eq!(
+            lines(&table, Some(20)),
+            vec!["blåbær  A rather lo…", "日本    Short"]
+        );
+    }
+
+    #[test]
+    fn it_prints_empty_tables() {
+        let mut table = Table::new();
+        table.add_header(String::from("NAME"));
+
+        assert_eq!(lines(&table, None), vec!["NAME"]);
     }
 }
@@ -0,0 +1,547 @@
+use std::cmp::Ordering;
+use std::io::{self, IsTerminal, Write};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::kill;
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg, Termios};
+use nix::unistd::{read, Pid};
+use psutil::memory::virtual_memory;
+use unicode_width::UnicodeWidthStr;
+
+use crate::config::BOX_NAME;
+use crate::init::Service;
+use crate::jobs::parse_signal;
+use crate::output::Output;
+use crate::procs::{self, format_bytes, format_duration, Proc, Sampler};
+use crate::table::{terminal_size, truncate};
+
+/// How often to look for a resized terminal while waiting for input
+const RESIZE_CHECK: Duration = Duration::from_millis(250);
+
+const HELP: &str = "↑↓ select  P/M/N/U sort  R reverse  k signal  space refresh  q quit";
+
+/// Redraw the box's processes every `delay` seconds until `q` is pressed
+pub fn top(output: &Output, delay: f64) -> ExitCode {
+    let (sort, descending) = match &output.sort {
+        None => (Sort::Cpu, true),
+        Some(sort) => {
+            let (field, descending) = match sort.strip_prefix('-') {
+                Some(field) => (field, true),
+                None => (sort.as_str(), false),
+            };
+            let Some(sort) = Sort::parse(field) else {
+                let names: Vec<&str> = Sort::FIELDS.iter().map(|x| x.0).collect();
+                eprintln!(
+                    "Unknown field '{}', expected one of: {}",
+                    field,
+                    names.join(", ")
+                );
+                return ExitCode::FAILURE;
+            };
+            (sort, descending)
+        }
+    };
+    let delay = match Duration::try_from_secs_f64(delay) {
+        Ok(delay) if !delay.is_zero() => delay,
+        _ => {
+            eprintln!("The delay must be a positive number of seconds");
+            return ExitCode::FAILURE;
+        }
+    };
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        eprintln!("top needs a terminal");
+        return ExitCode::FAILURE;
+    }
+    let Some(service) = Service::from_existing() else {
+        eprintln!("nixbox not running");
+        return ExitCode::FAILURE;
+    };
+
+    let mut top = Top {
+        boxes: vec![Watched {
+            name: BOX_NAME,
+            service_pid: service.pid,
+            sampler: Sampler::new(),
+            procs: vec![],
+            uptime: None,
+        }],
+        total_memory: virtual_memory().map(|x| x.total()).unwrap_or_default(),
+        sort,
+        descending,
+        selected: None,
+        scroll: 0,
+        page: 1,
+        prompt: None,
+        message: String::new(),
+    };
+
+    let terminal = match RawTerminal::enter() {
+        Ok(terminal) => terminal,
+        Err(err) => {
+            eprintln!("Could not set up the terminal: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let result = top.run(delay);
+    drop(terminal);
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Puts the terminal in raw mode on the alternate screen, and back when
+/// dropped
+struct RawTerminal {
+    saved: Termios,
+}
+
+impl RawTerminal {
+    fn enter() -> nix::Result<Self> {
+        let stdin = io::stdin();
+        let saved = tcgetattr(&stdin)?;
+        let mut raw = saved.clone();
+        cfmakeraw(&mut raw);
+        tcsetattr(&stdin, SetArg::TCSANOW, &raw)?;
+        print!("\x1b[?1049h\x1b[?25l");
+        let _ = io::stdout().flush();
+        Ok(Self { saved })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        let _ = tcsetattr(io::stdin(), SetArg::TCSANOW, &self.saved);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Sort {
+    Pid,
+    User,
+    Cpu,
+    Memory,
+    Command,
+}
+
+impl Sort {
+    /// The field names of `brief status` that processes can be sorted by
+    const FIELDS: [(&'static str, Sort); 5] = [
+        ("pid", Sort::Pid),
+        ("user", Sort::User),
+        ("cpu_percent", Sort::Cpu),
+        ("rss", Sort::Memory),
+        ("cmdline", Sort::Command),
+    ];
+
+    fn parse(field: &str) -> Option<Self> {
+        Self::FIELDS.iter().find(|x| x.0 == field).map(|x| x.1)
+    }
+
+    fn compare(self, a: &Proc, b: &Proc) -> Ordering {
+        match self {
+            Sort::Pid => a.pid.cmp(&b.pid),
+            Sort::User => a.user.cmp(&b.user),
+            Sort::Cpu => a.cpu_percent.total_cmp(&b.cpu_percent),
+            Sort::Memory => a.rss.cmp(&b.rss),
+            Sort::Command => a.cmdline.cmp(&b.cmdline),
+        }
+        .then(a.pid.cmp(&b.pid))
+    }
+
+    fn header(self) -> &'static str {
+        match self {
+            Sort::Pid => "PID",
+            Sort::User => "USER",
+            Sort::Cpu => "%CPU",
+            Sort::Memory => "RSS",
+            Sort::Command => "COMMAND",
+        }
+    }
+}
+
+/// A running box and the latest snapshot of its processes. There is one box
+/// per user for now, but totals are kept per box so that the view stays the
+/// same once several can run.
+struct Watched {
+    name: &'static str,
+    service_pid: i32,
+    sampler: Sampler,
+    procs: Vec<Proc>,
+    uptime: Option<Duration>,
+}
+
+struct Top {
+    boxes: Vec<Watched>,
+    /// Memory of the host, for `%MEM`
+    total_memory: u64,
+    sort: Sort,
+    descending: bool,
+    /// PID of the highlighted process, kept across updates and re-sorting
+    selected: Option<i32>,
+    /// Index of the first process row on screen
+    scroll: usize,
+    /// Number of process rows on screen
+    page: usize,
+    /// Signal being typed after `k`
+    prompt: Option<String>,
+    /// Outcome of the last action, shown on the bottom line
+    message: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Key {
+    Char(char),
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Escape,
+    Backspace,
+    Interrupt,
+}
+
+impl Top {
+    fn run(&mut self, delay: Duration) -> Result<(), String> {
+        let mut next = Instant::now();
+        let mut drawn_size = None;
+        let mut dirty = true;
+        loop {
+            if Instant::now() >= next {
+                self.refresh()?;
+                next = Instant::now() + delay;
+                dirty = true;
+            }
+            let size = terminal_size().unwrap_or((80, 24));
+            if dirty || drawn_size != Some(size) {
+                self.draw(size);
+                drawn_size = Some(size);
+                dirty = false;
+            }
+
+            let timeout = next
+                .saturating_duration_since(Instant::now())
+                .min(RESIZE_CHECK);
+            for key in read_keys(timeout) {
+                dirty = true;
+                match self.key(key) {
+                    Some(Action::Quit) => return Ok(()),
+                    Some(Action::Refresh) => next = Instant::now(),
+                    None => (),
+                }
+            }
+        }
+    }
+
+    fn refresh(&mut self) -> Result<(), String> {
+        for watched in &mut self.boxes {
+            let Some(procs) = watched.sampler.sample(watched.service_pid) else {
+                return Err(format!("{} stopped", watched.name));
+            };
+            watched.procs = procs;
+            watched.uptime = procs::uptime(watched.service_pid);
+        }
+        let rows = self.rows();
+        if !rows.iter().any(|x| Some(x.pid) == self.selected) {
+            self.selected = rows.first().map(|x| x.pid);
+        }
+        Ok(())
+    }
+
+    /// Processes of all boxes in the chosen order
+    fn rows(&self) -> Vec<&Proc> {
+        let mut rows: Vec<&Proc> = self.boxes.iter().flat_map(|x| &x.procs).collect();
+        rows.sort_by(|a, b| match self.descending {
+            true => self.sort.compare(b, a),
+            false => self.sort.compare(a, b),
+        });
+        rows
+    }
+
+    fn key(&mut self, key: Key) -> Option<Action> {
+        if let Some(prompt) = &mut self.prompt {
+            match key {
+                Key::Char(c) => prompt.push(c),
+                Key::Backspace => {
+                    prompt.pop();
+                }
+                Key::Escape | Key::Interrupt => self.prompt = None,
+                Key::Enter => {
+                    let text = self.prompt.take().unwrap_or_default();
+                    self.signal(text.trim());
+                    return Some(Action::Refresh);
+                }
+                _ => (),
+            }
+            return None;
+        }
+
+        let sort = |top: &mut Self, sort: Sort| {
+            top.sort = sort;
+            top.descending = matches!(sort, Sort::Cpu | Sort::Memory);
+        };
+        match key {
+            Key::Char('q') | Key::Interrupt => return Some(Action::Quit),
+            Key::Char(' ') => return Some(Action::Refresh),
+            Key::Up => self.select(|index, _| index.saturating_sub(1)),
+            Key::Down => self.select(|index, _| index + 1),
+            Key::PageUp => self.select(|index, page| index.saturating_sub(page)),
+            Key::PageDown => self.select(|index, page| index + page),
+            Key::Home => self.select(|_, _| 0),
+            Key::End => self.select(|_, _| usize::MAX),
+            Key::Char('P') => sort(self, Sort::Cpu),
+            Key::Char('M') => sort(self, Sort::Memory),
+            Key::Char('N') => sort(self, Sort::Pid),
+            Key::Char('U') => sort(self, Sort::User),
+            Key::Char('R') => self.descending = !self.descending,
+            Key::Char('k') if self.selected.is_some() => {
+                self.prompt = Some(String::new());
+                self.message.clear();
+            }
+            _ => (),
+        }
+        None
+    }
+
+    /// Move the selection to `to(current index, page size)`
+    fn select(&mut self, to: impl Fn(usize, usize) -> usize) {
+        let rows = self.rows();
+        if rows.is_empty() {
+            return;
+        }
+        let index = rows
+            .iter()
+            .position(|x| Some(x.pid) == self.selected)
+            .unwrap_or(0);
+        let index = to(index, self.page).min(rows.len() - 1);
+        self.selected = Some(rows[index].pid);
+    }
+
+    /// Send the signal named `text`, TERM if empty, to the selected process
+    fn signal(&mut self, text: &str) {
+        let text = match text.is_empty() {
+            true => "TERM",
+            false => text,
+        };
+        let Some(signal) = parse_signal(text) else {
+            self.message = format!("Unknown signal '{}'", text);
+            return;
+        };
+        let Some(pid) = self.selected else {
+            return;
+        };
+        // The process may have exited since the last sample, and its PID
+        // been given to a process outside the box
+        if !self.boxes.iter().any(|x| procs::in_box(x.service_pid, pid)) {
+            self.message = format!("Process {} is no longer in the box", pid);
+            return;
+        }
+        self.message = match kill(Pid::from_raw(pid), signal) {
+            Ok(()) => format!("Sent {} to {}", signal, pid),
+            Err(err) => format!("Could not signal {}: {}", pid, err),
+        };
+    }
+
+    fn draw(&mut self, (columns, lines): (usize, usize)) {
+        let mut screen: Vec<String> = vec![];
+        for watched in &self.boxes {
+            let cpu: f32 = watched.procs.iter().map(|x| x.cpu_percent).sum();
+            let rss: u64 = watched.procs.iter().map(|x| x.rss).sum();
+            let uptime = watched
+                .uptime
+                .map(|x| format!("up {}, ", format_duration(x)))
+                .unwrap_or_default();
+            screen.push(format!(
+                "{}: {}{} processes, {:.1}% CPU, {} RSS ({:.1}% MEM)",
+                watched.name,
+                uptime,
+                watched.procs.len(),
+                cpu,
+                format_bytes(rss),
+                self.memory_percent(rss)
+            ));
+        }
+        screen.push(format!(
+            "Sorted by {} {}  {}",
+            self.sort.header(),
+            match self.descending {
+                true => "↓",
+                false => "↑",
+            },
+            HELP
+        ));
+        screen.push(String::new());
+        let header = format!(
+            "{:>7} {:<8} {} {:>5} {:>5} {:>7}  COMMAND",
+            "PID", "USER", "S", "%CPU", "%MEM", "RSS"
+        );
+        let header_line = screen.len();
+        screen.push(header);
+
+        // Keep the last line for the prompt and messages
+        self.page = lines.saturating_sub(screen.len() + 1).max(1);
+        let rows = self.rows();
+        let selected = rows.iter().position(|x| Some(x.pid) == self.selected);
+        let mut scroll = self.scroll;
+        if let Some(selected) = selected {
+            if selected < scroll {
+                scroll = selected;
+            } else if selected >= scroll + self.page {
+                scroll = selected + 1 - self.page;
+            }
+        }
+        scroll = scroll.min(rows.len().saturating_sub(self.page));
+
+        let first_row = screen.len();
+        for proc in rows.iter().skip(scroll).take(self.page) {
+            screen.push(format!(
+                "{:>7} {:<8} {} {:>5.1} {:>5.1} {:>7}  {}",
+                proc.pid,
+                truncate(&proc.user, 8),
+                proc.state,
+                proc.cpu_percent,
+                self.memory_percent(proc.rss),
+                format_bytes(proc.rss),
+                proc.cmdline.replace(['\t', '\n', '\r'], " ")
+            ));
+        }
+        let highlighted = selected.map(|x| first_row + x - scroll);
+        self.scroll = scroll;
+        while screen.len() < lines.saturating_sub(1) {
+            screen.push(String::new());
+        }
+        screen.push(match &self.prompt {
+            Some(prompt) => format!(
+                "Signal for PID {} (TERM): {}",
+                self.selected.unwrap_or_default(),
+                prompt
+            ),
+            None => self.message.clone(),
+        });
+
+        let mut out = String::from("\x1b[H");
+        for (index, line) in screen.iter().enumerate() {
+            let line = truncate(line, columns);
+            if index == header_line {
+                out.push_str(&format!("\x1b[1m{}\x1b[0m", line));
+            } else if Some(index) == highlighted {
+                let padding = " ".repeat(columns.saturating_sub(line.width()));
+                out.push_str(&format!("\x1b[7m{}{}\x1b[0m", line, padding));
+            } else {
+                out.push_str(&line);
+            }
+            out.push_str("\x1b[K");
+            // Raw mode doesn't turn newlines into line breaks, and a break
+            // after the last line would scroll the screen
+            if index + 1 < screen.len() {
+                out.push_str("\r\n");
+            }
+        }
+        out.push_str("\x1b[J");
+        let mut stdout = io::stdout();
+        let _ = stdout
+            .write_all(out.as_bytes())
+            .and_then(|_| stdout.flush());
+    }
+
+    fn memory_percent(&self, rss: u64) -> f64 {
+        match self.total_memory {
+            0 => 0.0,
+            total => rss as f64 / total as f64 * 100.0,
+        }
+    }
+}
+
+enum Action {
+    Quit,
+    Refresh,
+}
+
+/// Keys pressed within `timeout`
+fn read_keys(timeout: Duration) -> Vec<Key> {
+    let mut fds = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    if unsafe { libc::poll(&mut fds, 1, timeout.as_millis() as libc::c_int) } <= 0 {
+        return vec![];
+    }
+    let mut buf = [0u8; 64];
+    match read(libc::STDIN_FILENO, &mut buf) {
+        Ok(len) if len > 0 => parse_keys(&buf[..len]),
+        // The terminal went away
+        _ => vec![Key::Interrupt],
+    }
+}
+
+fn parse_keys(bytes: &[u8]) -> Vec<Key> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut chars = text.chars().peekable();
+    let mut keys = vec![];
+    while let Some(c) = chars.next() {
+        let key = match c {
+            '\x1b' if chars.peek() == Some(&'[') || chars.peek() == Some(&'O') => {
+                chars.next();
+                // CSI sequences end with a letter or `~`, after parameters
+                let mut params = String::new();
+                let end = loop {
+                    match chars.next() {
+                        Some(c) if c.is_ascii_digit() || c == ';' => params.push(c),
+                        end => break end,
+                    }
+                };
+                match (params.as_str(), end) {
+                    (_, Some('A')) => Key::Up,
+                    (_, Some('B')) => Key::Down,
+                    (_, Some('H')) | ("1" | "7", Some('~')) => Key::Home,
+                    (_, Some('F')) | ("4" | "8", Some('~')) => Key::End,
+                    ("5", Some('~')) => Key::PageUp,
+                    ("6", Some('~')) => Key::PageDown,
+                    _ => continue,
+                }
+            }
+            '\x1b' => Key::Escape,
+            '\x03' => Key::Interrupt,
+            '\r' | '\n' => Key::Enter,
+            '\x7f' | '\x08' => Key::Backspace,
+            c if c.is_control() => continue,
+            c => Key::Char(c),
+        };
+        keys.push(key);
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_keys() {
+        assert_eq!(
+            parse_keys(b"q\x1b[A\x1b[6~\x1bOBk9\r\x1b\x7f\x1b[1;5C"),
+            vec![
+                Key::Char('q'),
+                Key::Up,
+                Key::PageDown,
+                Key::Down,
+                Key::Char('k'),
+                Key::Char('9'),
+                Key::Enter,
+                Key::Escape,
+                Key::Backspace,
+            ]
+        );
+    }
+}
@@ -0,0 +1,979 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ExitCode, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::{getuid, Pid};
+use zbus::blocking::{Connection, Proxy};
+
+use crate::command::{command, exit_code};
+use crate::config::Config;
+use crate::dbus;
+use crate::init::xdg_runtime_dir;
+use crate::limits::Limits;
+use crate::output::{Format, Listing, Output};
+use crate::util::{parse_env_file, KeyFile};
+
+/// Like systemd, give up on a unit that is started this often
+const START_LIMIT_BURST: usize = 5;
+const START_LIMIT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Time a unit gets to exit after SIGTERM before it is killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// When to start a unit's command again after it exits
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Restart {
+    No,
+    Always,
+    OnSuccess,
+    OnFailure,
+    OnAbnormal,
+}
+
+impl Restart {
+    fn parse(text: &str) -> Option<Self> {
+        match text {
+            "no" => Some(Self::No),
+            "always" => Some(Self::Always),
+            "on-success" => Some(Self::OnSuccess),
+            "on-failure" => Some(Self::OnFailure),
+            // Without watchdogs, aborts are the only abnormal exits
+            "on-abnormal" | "on-abort" | "on-watchdog" => Some(Self::OnAbnormal),
+            _ => None,
+        }
+    }
+
+    /// Whether to restart after exiting with `code`, as returned by `exit_code`
+    fn applies(&self, code: i32) -> bool {
+        match self {
+            Self::No => false,
+            Self::Always => true,
+            Self::OnSuccess => code == 0,
+            Self::OnFailure => code != 0,
+            Self::OnAbnormal => code > 128,
+        }
+    }
+}
+
+/// An `ExecStart=` style command line with its prefixes removed
+#[derive(Clone, Debug, PartialEq)]
+struct ExecCommand {
+    line: String,
+    /// `-`: a failing command doesn't fail the unit
+    ignore_failure: bool,
+    /// `:`: no environment variable substitution
+    literal: bool,
+    /// `@`: the second word is passed as argv[0]
+    arg0: bool,
+}
+
+impl ExecCommand {
+    fn parse(text: &str) -> Self {
+        let line = text.trim_start_matches(['@', '-', ':', '+', '!']);
+        let prefixes = &text[..text.len() - line.len()];
+        Self {
+            line: line.to_string(),
+            ignore_failure: prefixes.contains('-'),
+            literal: prefixes.contains(':'),
+            arg0: prefixes.contains('@'),
+        }
+    }
+
+    /// The program, its argv[0] when it is overridden, and its arguments
+    fn argv(&self, env: &HashMap<String, String>) -> Option<(String, Option<String>, Vec<String>)> {
+        let mut words = split_words(&self.line, Some(env).filter(|_| !self.literal))?.into_iter();
+        let program = words.next()?;
+        let arg0 = match self.arg0 {
+            true => Some(words.next()?),
+            false => None,
+        };
+        Some((program, arg0, words.collect()))
+    }
+}
+
+/// A systemd user service. Only what is needed to run simple services is
+/// supported: `ExecStartPre=`, `ExecStart=`, `Environment=`,
+/// `EnvironmentFile=`, `WorkingDirectory=`, `Restart=` and `RestartSec=`.
+#[derive(Clone, Debug)]
+pub struct Unit {
+    pub name: String,
+    pub description: String,
+    pub path: PathBuf,
+    /// Wanted by `default.target`, so started with the box
+    pub enabled: bool,
+    exec_start_pre: Vec<ExecCommand>,
+    exec_start: Vec<ExecCommand>,
+    environment: Vec<(String, String)>,
+    /// Paths and whether they may be missing
+    environment_files: Vec<(PathBuf, bool)>,
+    working_directory: Option<PathBuf>,
+    restart: Restart,
+    restart_sec: Duration,
+}
+
+impl Unit {
+    fn parse(name: &str, path: PathBuf, text: &str, enabled: bool) -> Result<Self, String> {
+        let keyfile = KeyFile::parse_unit(&expand_specifiers(text, name));
+        if keyfile.group("Service").is_none() {
+            return Err(String::from("no [Service] section"));
+        }
+        let last = |key: &str| values(&keyfile, "Service", key).pop();
+
+        let oneshot = match last("Type").as_deref() {
+            None | Some("simple" | "exec" | "notify" | "dbus" | "idle") => false,
+            Some("oneshot") => true,
+            Some(other) => return Err(format!("Type={} is not supported", other)),
+        };
+        let exec_start: Vec<ExecCommand> = values(&keyfile, "Service", "ExecStart")
+            .iter()
+            .map(|x| ExecCommand::parse(x))
+            .collect();
+        match exec_start.len() {
+            0 => return Err(String::from("no ExecStart=")),
+            1 => (),
+            _ if oneshot => (),
+            _ => return Err(String::from("more than one ExecStart=")),
+        }
+        let exec_start_pre: Vec<ExecCommand> = values(&keyfile, "Service", "ExecStartPre")
+            .iter()
+            .map(|x| ExecCommand::parse(x))
+            .collect();
+        let empty = HashMap::new();
+        for exec in exec_start.iter().chain(&exec_start_pre) {
+            if exec.argv(&empty).is_none() {
+                return Err(format!("invalid command line '{}'", exec.line));
+            }
+        }
+
+        let mut environment = vec![];
+        for line in values(&keyfile, "Service", "Environment") {
+            let words =
+                split_words(&line, None).ok_or_else(|| format!("invalid Environment={}", line))?;
+            for word in words {
+                if let Some((key, val)) = word.split_once('=') {
+                    environment.push((key.to_string(), val.to_string()));
+                }
+            }
+        }
+        let environment_files = values(&keyfile, "Service", "EnvironmentFile")
+            .iter()
+            .map(|x| match x.strip_prefix('-') {
+                Some(path) => (PathBuf::from(path), true),
+                None => (PathBuf::from(x), false),
+            })
+            .collect();
+
+        let working_directory = last("WorkingDirectory").map(|dir| {
+            let dir = dir.strip_prefix('-').unwrap_or(&dir);
+            match dir {
+                "~" => PathBuf::from(env::var_os("HOME").unwrap_or_else(|| "/".into())),
+                _ => PathBuf::from(dir),
+            }
+        });
+        let restart = match last("Restart") {
+            Some(text) => {
+                Restart::parse(&text).ok_or_else(|| format!("invalid Restart={}", text))?
+            }
+            None => Restart::No,
+        };
+        let restart_sec = match last("RestartSec") {
+            Some(text) => {
+                parse_duration(&text).ok_or_else(|| format!("invalid RestartSec={}", text))?
+            }
+            None => Duration::from_millis(100),
+        };
+
+        let wanted_by = values(&keyfile, "Install", "WantedBy");
+        Ok(Self {
+            name: name.to_string(),
+            description: keyfile
+                .get("Unit", "Description")
+                .unwrap_or_default()
+                .to_string(),
+            path,
+            enabled: enabled
+                || wanted_by
+                    .iter()
+                    .flat_map(|x| x.split_whitespace())
+                    .any(|x| x == "default.target"),
+            exec_start_pre,
+            exec_start,
+            environment,
+            environment_files,
+            working_directory,
+            restart,
+            restart_sec,
+        })
+    }
+
+    /// Load the unit `name`, with or without the `.service` suffix
+    pub fn load(config: &Config, name: &str) -> Result<Self, String> {
+        let name = unit_name(name);
+        let dirs = unit_dirs(config);
+        let Some(path) = unit_paths(&dirs).remove(&name) else {
+            return Err(format!("No unit named '{}'", name));
+        };
+        Self::read(&dirs, &name, path).map_err(|err| format!("{}: {}", name, err))
+    }
+
+    /// All services in the box, ordered by name. Units that can't be loaded
+    /// are skipped with a warning.
+    pub fn find_all(config: &Config) -> Vec<Self> {
+        let dirs = unit_dirs(config);
+        unit_paths(&dirs)
+            .into_iter()
+            .filter_map(|(name, path)| {
+                Self::read(&dirs, &name, path)
+                    .map_err(|err| eprintln!("Skipping unit {}: {}", name, err))
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Read a unit together with its `<name>.d/*.conf` drop-ins
+    fn read(dirs: &[PathBuf], name: &str, path: PathBuf) -> Result<Self, String> {
+        let mut text = fs::read_to_string(&path).map_err(|err| err.to_string())?;
+        let mut dropins: BTreeMap<String, PathBuf> = BTreeMap::new();
+        for dir in dirs {
+            let Ok(entries) = fs::read_dir(dir.join(format!("{}.d", name))) else {
+                continue;
+            };
+            for entry in entries.filter_map(|x| x.ok()) {
+                let path = entry.path();
+                if path.extension().map(|x| x == "conf").unwrap_or(false) {
+                    dropins.insert(entry.file_name().to_string_lossy().into_owned(), path);
+                }
+            }
+        }
+        for path in dropins.values() {
+            text.push('\n');
+            text.push_str(&fs::read_to_string(path).map_err(|err| err.to_string())?);
+        }
+
+        let enabled = dirs
+            .iter()
+            .any(|dir| dir.join("default.target.wants").join(name).exists());
+        Self::parse(name, path, &text, enabled)
+    }
+
+    /// The unit's own environment, from `Environment=` and then
+    /// `EnvironmentFile=`, which is read every time the unit starts
+    fn environment(&self) -> Result<Vec<(String, String)>, String> {
+        let mut environment = self.environment.clone();
+        for (path, optional) in &self.environment_files {
+            let text = match fs::read_to_string(path) {
+                Ok(text) => text,
+                Err(_) if *optional => continue,
+                Err(err) => return Err(format!("Could not read '{}': {}", path.display(), err)),
+            };
+            let vars = parse_env_file(&text)
+                .map_err(|line| format!("Invalid line {} in '{}'", line, path.display()))?;
+            environment.extend(vars);
+        }
+        Ok(environment)
+    }
+}
+
+pub fn unit_name(name: &str) -> String {
+    match name.ends_with(".service") {
+        true => name.to_string(),
+        false => format!("{}.service", name),
+    }
+}
+
+/// Directories with user units, in increasing order of precedence: the box's
+/// NixOS configuration, then home-manager's
+fn unit_dirs(config: &Config) -> Vec<PathBuf> {
+    let mut dirs = vec![];
+    if let Some(current_system) = &config.current_system {
+        dirs.push(current_system.join("etc/systemd/user"));
+    }
+    dirs.push(
+        config
+            .xdg_state_home()
+            .join("nix/profiles/home-manager/home-files/.config/systemd/user"),
+    );
+    dirs.push(config.xdg_config_home().join("systemd/user"));
+    dirs
+}
+
+/// Services by name. Templates are left out since nothing instantiates them.
+fn unit_paths(dirs: &[PathBuf]) -> BTreeMap<String, PathBuf> {
+    let mut paths = BTreeMap::new();
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(|x| x.ok()) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Units masked with a link to /dev/null are not files
+            if name.ends_with(".service") && !name.contains('@') && entry.path().is_file() {
+                paths.insert(name, entry.path());
+            }
+        }
+    }
+    paths
+}
+
+/// Values of a key that may be repeated, in every `[group]` section. As in
+/// systemd, an empty value resets the list.
+fn values(keyfile: &KeyFile, group: &str, key: &str) -> Vec<String> {
+    let mut values = vec![];
+    for entry in keyfile.groups().filter(|x| x.name() == group) {
+        for (k, val) in entry.entries() {
+            match (k == key, val.is_empty()) {
+                (true, true) => values.clear(),
+                (true, false) => values.push(val.clone()),
+                _ => (),
+            }
+        }
+    }
+    values
+}
+
+/// Replace the `%` specifiers systemd supports in unit files that make sense
+/// in the box
+fn expand_specifiers(text: &str, name: &str) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => expanded.push('%'),
+            Some('n') => expanded.push_str(name),
+            Some('N') | Some('p') => expanded.push_str(name.trim_end_matches(".service")),
+            Some('h') => expanded.push_str(&env::var("HOME").unwrap_or_default()),
+            Some('u') => expanded.push_str(&env::var("USER").unwrap_or_default()),
+            Some('U') => expanded.push_str(&getuid().to_string()),
+            Some('t') => expanded.push_str(&xdg_runtime_dir().to_string_lossy()),
+            Some(other) => {
+                expanded.push('%');
+                expanded.push(other);
+            }
+            None => expanded.push('%'),
+        }
+    }
+    expanded
+}
+
+/// Split a command line the way systemd does: words are separated by
+/// whitespace and may be quoted, and backslash escapes are decoded. With
+/// `env`, `${NAME}` is replaced by the variable's value, and a word that is
+/// just `$NAME` by the value split at whitespace.
+fn split_words(text: &str, env: Option<&HashMap<String, String>>) -> Option<Vec<String>> {
+    let mut words = vec![];
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Some(words);
+        }
+
+        if let Some(env) = env {
+            let rest: String = chars.clone().take_while(|c| !c.is_whitespace()).collect();
+            if let Some(name) = rest.strip_prefix('$').filter(|x| is_var_name(x)) {
+                if let Some(val) = env.get(name) {
+                    words.extend(val.split_whitespace().map(String::from));
+                }
+                chars.nth(rest.chars().count() - 1);
+                continue;
+            }
+        }
+
+        let mut word = String::new();
+        let mut quote = None;
+        while let Some(c) = chars.next() {
+            match (c, quote) {
+                (c, None) if c.is_whitespace() => break,
+                ('"' | '\'', None) => quote = Some(c),
+                (c, Some(q)) if c == q => quote = None,
+                ('\\', _) => word.push(match chars.next()? {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    other => other,
+                }),
+                ('$', q) if env.is_some() && q != Some('\'') && chars.peek() == Some(&'{') => {
+                    chars.next();
+                    let mut name = String::new();
+                    loop {
+                        match chars.next()? {
+                            '}' => break,
+                            c => name.push(c),
+                        }
+                    }
+                    if let Some(val) = env.and_then(|x| x.get(&name)) {
+                        word.push_str(val);
+                    }
+                }
+                ('$', q) if env.is_some() && q != Some('\'') && chars.peek() == Some(&'$') => {
+                    chars.next();
+                    word.push('$');
+                }
+                (c, _) => word.push(c),
+            }
+        }
+        if quote.is_some() {
+            return None;
+        }
+        words.push(word);
+    }
+}
+
+fn is_var_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parse a systemd time span like `5`, `500ms` or `1min 30s`. Plain numbers
+/// are seconds.
+fn parse_duration(text: &str) -> Option<Duration> {
+    let mut total = Duration::ZERO;
+    for part in text.split_whitespace() {
+        let index = part
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(part.len());
+        let (number, unit) = part.split_at(index);
+        let number: f64 = number.parse().ok()?;
+        let secs = match unit {
+            "ms" | "msec" => number / 1000.0,
+            "" | "s" | "sec" | "second" | "seconds" => number,
+            "m" | "min" | "minute" | "minutes" => number * 60.0,
+            "h" | "hr" | "hour" | "hours" => number * 3600.0,
+            _ => return None,
+        };
+        total += Duration::from_secs_f64(secs);
+    }
+    Some(total).filter(|_| !text.trim().is_empty())
+}
+
+/// Where the output of unit `name` goes
+pub fn log_path(config: &Config, name: &str) -> PathBuf {
+    config
+        .xdg_state_home()
+        .join("brief/units")
+        .join(format!("{}.log", unit_name(name)))
+}
+
+/// (name, description, path, enabled, status, pid) of a unit
+pub type UnitInfo = (String, String, String, bool, String, u32);
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Status {
+    #[default]
+    Inactive,
+    Starting,
+    Running,
+    Restarting,
+    Stopping,
+    /// The command exited and isn't restarted
+    Exited(i32),
+    Failed(String),
+}
+
+impl Status {
+    fn is_active(&self) -> bool {
+        matches!(
+            self,
+            Self::Starting | Self::Running | Self::Restarting | Self::Stopping
+        )
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Inactive => write!(f, "inactive"),
+            Self::Starting => write!(f, "starting"),
+            Self::Running => write!(f, "running"),
+            Self::Restarting => write!(f, "restarting"),
+            Self::Stopping => write!(f, "stopping"),
+            Self::Exited(code) => write!(f, "exited ({})", code),
+            Self::Failed(reason) => write!(f, "failed: {}", reason),
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    status: Status,
+    pid: Option<u32>,
+    stopping: bool,
+}
+
+/// Runs the box's systemd user services inside the service process, since
+/// there is no systemd in the box
+#[derive(Clone)]
+pub struct Supervisor {
+    config: Config,
+    units: Arc<Mutex<HashMap<String, State>>>,
+}
+
+impl Supervisor {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            units: Arc::default(),
+        }
+    }
+
+    /// Start the units wanted by `default.target`
+    pub fn start_enabled(&self) {
+        for unit in Unit::find_all(&self.config) {
+            if !unit.enabled {
+                continue;
+            }
+            let name = unit.name.clone();
+            match self.start_unit(unit) {
+                Ok(()) => println!("Started unit {}", name),
+                Err(err) => eprintln!("Could not start unit {}: {}", name, err),
+            }
+        }
+    }
+
+    pub fn start(&self, name: &str) -> Result<(), String> {
+        self.start_unit(Unit::load(&self.config, name)?)
+    }
+
+    fn start_unit(&self, unit: Unit) -> Result<(), String> {
+        let mut units = self.units.lock().unwrap();
+        let state = units.entry(unit.name.clone()).or_default();
+        if state.status.is_active() {
+            return Err(format!("Unit '{}' is already active", unit.name));
+        }
+        *state = State {
+            status: Status::Starting,
+            ..State::default()
+        };
+        drop(units);
+
+        let supervisor = self.clone();
+        thread::spawn(move || supervisor.supervise(unit));
+        Ok(())
+    }
+
+    /// Stop a unit: its process group gets SIGTERM, then SIGKILL if it is
+    /// still around after `STOP_TIMEOUT`
+    pub fn stop(&self, name: &str) -> Result<(), String> {
+        let name = unit_name(name);
+        let mut units = self.units.lock().unwrap();
+        let Some(state) = units.get_mut(&name).filter(|x| x.status.is_active()) else {
+            return Err(format!("Unit '{}' is not active", name));
+        };
+        state.stopping = true;
+        state.status = Status::Stopping;
+
+        if let Some(pid) = state.pid {
+            let _ = killpg(Pid::from_raw(pid as i32), Signal::SIGTERM);
+            let units = self.units.clone();
+            thread::spawn(move || {
+                sleep(STOP_TIMEOUT);
+                if units.lock().unwrap().get(&name).and_then(|x| x.pid) == Some(pid) {
+                    let _ = killpg(Pid::from_raw(pid as i32), Signal::SIGKILL);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// All units as (name, description, path, enabled, status, pid)
+    pub fn list(&self) -> Vec<UnitInfo> {
+        let units = self.units.lock().unwrap();
+        Unit::find_all(&self.config)
+            .into_iter()
+            .map(|unit| {
+                let state = units.get(&unit.name);
+                (
+                    unit.name.clone(),
+                    unit.description,
+                    unit.path.to_string_lossy().into_owned(),
+                    unit.enabled,
+                    state
+                        .map(|x| x.status.to_string())
+                        .unwrap_or_else(|| Status::Inactive.to_string()),
+                    state.and_then(|x| x.pid).unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    fn set_status(&self, name: &str, status: Status) {
+        let mut units = self.units.lock().unwrap();
+        let state = units.entry(name.to_string()).or_default();
+        // A stopped unit is inactive, however its command exited
+        state.status = match state.stopping {
+            true => Status::Inactive,
+            false => status,
+        };
+        state.stopping = false;
+        state.pid = None;
+    }
+
+    fn is_stopping(&self, name: &str) -> bool {
+        self.units
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|x| x.stopping)
+            .unwrap_or(false)
+    }
+
+    fn supervise(&self, unit: Unit) {
+        let path = log_path(&self.config, &unit.name);
+        let log = match open_log(&path) {
+            Ok(log) => log,
+            Err(err) => {
+                let reason = format!("Could not open '{}': {}", path.display(), err);
+                return self.set_status(&unit.name, Status::Failed(reason));
+            }
+        };
+
+        let mut starts: VecDeque<Instant> = VecDeque::new();
+        loop {
+            let now = Instant::now();
+            starts.retain(|x| now.duration_since(*x) < START_LIMIT_INTERVAL);
+            if starts.len() >= START_LIMIT_BURST {
+                let reason = String::from("restarted too often");
+                return self.set_status(&unit.name, Status::Failed(reason));
+            }
+            starts.push_back(now);
+
+            let code = match self.run(&unit, &log) {
+                Ok(code) => code,
+                Err(err) => {
+                    let _ = writeln!(&log, "[brief] {}", err);
+                    return self.set_status(&unit.name, Status::Failed(err));
+                }
+            };
+            let _ = writeln!(&log, "[brief] {} exited with code {}", unit.name, code);
+
+            if self.is_stopping(&unit.name) || !unit.restart.applies(code) {
+                let status = match code {
+                    0 => Status::Exited(0),
+                    _ => Status::Failed(format!("exited with code {}", code)),
+                };
+                return self.set_status(&unit.name, status);
+            }
+
+            self.units
+                .lock()
+                .unwrap()
+                .entry(unit.name.clone())
+                .or_default()
+                .status = Status::Restarting;
+            let restart_at = Instant::now() + unit.restart_sec;
+            while Instant::now() < restart_at {
+                if self.is_stopping(&unit.name) {
+                    return self.set_status(&unit.name, Status::Inactive);
+                }
+                sleep(Duration::from_millis(100).min(unit.restart_sec));
+            }
+        }
+    }
+
+    /// Run the unit's commands once and return the exit code of the last one
+    fn run(&self, unit: &Unit, log: &File) -> Result<i32, String> {
+        let environment = unit.environment()?;
+        let mut env: HashMap<String, String> = self
+            .config
+            .env
+            .iter()
+            .map(|(k, v)| {
+                (
+                    k.to_string_lossy().into_owned(),
+                    v.to_string_lossy().into_owned(),
+                )
+            })
+            .collect();
+        env.extend(environment.iter().cloned());
+
+        for exec in &unit.exec_start_pre {
+            let code = self.run_exec(unit, exec, &env, &environment, log)?;
+            if code != 0 && !exec.ignore_failure {
+                return Err(format!("ExecStartPre= exited with code {}", code));
+            }
+        }
+
+        let mut code = 0;
+        for exec in &unit.exec_start {
+            code = self.run_exec(unit, exec, &env, &environment, log)?;
+            if code != 0 && !exec.ignore_failure {
+                break;
+            }
+            code = 0;
+            if self.is_stopping(&unit.name) {
+                break;
+            }
+        }
+        Ok(code)
+    }
+
+    fn run_exec(
+        &self,
+        unit: &Unit,
+        exec: &ExecCommand,
+        env: &HashMap<String, String>,
+        environment: &[(String, String)],
+        log: &File,
+    ) -> Result<i32, String> {
+        if self.is_stopping(&unit.name) {
+            return Ok(0);
+        }
+        let mut child = self.spawn(unit, exec, env, environment, log)?;
+        let pid = child.id();
+
+        let mut units = self.units.lock().unwrap();
+        let state = units.entry(unit.name.clone()).or_default();
+        if state.stopping {
+            let _ = killpg(Pid::from_raw(pid as i32), Signal::SIGTERM);
+        } else {
+            state.status = Status::Running;
+        }
+        state.pid = Some(pid);
+        drop(units);
+        let _ = writeln!(&*log, "[brief] Started {} (PID: {})", exec.line, pid);
+
+        let code = child
+            .wait()
+            .map(exit_code)
+            .map_err(|err| format!("Could not wait for {}: {}", exec.line, err))?;
+        if let Some(state) = self.units.lock().unwrap().get_mut(&unit.name) {
+            state.pid = None;
+        }
+        Ok(code)
+    }
+
+    fn spawn(
+        &self,
+        unit: &Unit,
+        exec: &ExecCommand,
+        env: &HashMap<String, String>,
+        environment: &[(String, String)],
+        log: &File,
+    ) -> Result<Child, String> {
+        let (program, arg0, args) = exec
+            .argv(env)
+            .ok_or_else(|| format!("Invalid command line '{}'", exec.line))?;
+        let output = || {
+            log.try_clone()
+                .map_err(|err| format!("Could not open log: {}", err))
+        };
+
+        let mut command = command(&self.config, &program, args, environment.iter().cloned());
+        if let Some(arg0) = arg0 {
+            command.arg0(arg0);
+        }
+        Limits::from_settings(&self.config.settings)
+            .unwrap_or_default()
+            .apply_to(&mut command);
+        let cwd = unit
+            .working_directory
+            .clone()
+            .or_else(|| env::var_os("HOME").map(PathBuf::from))
+            .unwrap_or_else(|| PathBuf::from("/"));
+        command
+            .current_dir(cwd)
+            .stdin(Stdio::null())
+            .stdout(output()?)
+            .stderr(output()?)
+            .process_group(0)
+            .spawn()
+            .map_err(|err| format!("failed to execute {}: {}", program, err))
+    }
+}
+
+fn open_log(path: &Path) -> io::Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn proxy() -> zbus::Result<Proxy<'static>> {
+    Proxy::new(&Connection::session()?, dbus::NAME, dbus::PATH, dbus::NAME)
+}
+
+/// Ask the service to start a unit
+pub fn start(name: &str) -> ExitCode {
+    match proxy().and_then(|x| x.call::<_, _, ()>("StartUnit", &(name,))) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Could not start unit '{}': {}", name, err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Ask the service to stop a unit
+pub fn stop(name: &str) -> ExitCode {
+    match proxy().and_then(|x| x.call::<_, _, ()>("StopUnit", &(name,))) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Could not stop unit '{}': {}", name, err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// List the box's units, or show one unit with the end of its log
+pub fn status(config: &Config, name: Option<&str>, output: &Output) -> ExitCode {
+    let units = match proxy().and_then(|x| x.call::<_, _, Vec<UnitInfo>>("ListUnits", &())) {
+        Ok(units) => units,
+        Err(err) => {
+            eprintln!("Could not list units: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let Some(name) = name else {
+        if units.is_empty() && output.format == Format::Table {
+            println!("No units");
+            return ExitCode::SUCCESS;
+        }
+        let mut listing = Listing::new(&[
+            ("name", "UNIT"),
+            ("enabled", "ENABLED"),
+            ("status", "STATUS"),
+            ("pid", "PID"),
+            ("description", "DESCRIPTION"),
+            ("path", ""),
+        ]);
+        listing.truncate(&["description"]);
+        for (name, description, path, enabled, status, pid) in units {
+            listing.add_row(vec![
+                name.into(),
+                (if enabled { "yes" } else { "no" }).into(),
+                status.into(),
+                Some(pid).filter(|x| *x != 0).into(),
+                description.into(),
+                path.into(),
+            ]);
+        }
+        return listing.print(output);
+    };
+
+    let name = unit_name(name);
+    let Some((name, description, path, enabled, status, pid)) =
+        units.into_iter().find(|x| x.0 == name)
+    else {
+        eprintln!("No unit named '{}'", name);
+        return ExitCode::FAILURE;
+    };
+    match description.is_empty() {
+        true => println!("{}", name),
+        false => println!("{} - {}", name, description),
+    }
+    println!(
+        "  Loaded: {} ({})",
+        path,
+        if enabled { "enabled" } else { "disabled" }
+    );
+    match pid {
+        0 => println!("  Status: {}", status),
+        _ => println!("  Status: {} (PID: {})", status, pid),
+    }
+
+    let log = fs::read_to_string(log_path(config, &name)).unwrap_or_default();
+    let lines: Vec<&str> = log.lines().collect();
+    if !lines.is_empty() {
+        println!();
+        for line in &lines[lines.len().saturating_sub(10)..] {
+            println!("{}", line);
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNIT: &str = r#"
+[Unit]
+Description=Syncthing for %n
+
+[Service]
+Environment="GREETING=hello world" LANG=C
+Environment=
+Environment=HOME=/home/user "PORT=8384"
+ExecStart=-@/bin/syncthing syncthing \
+    -no-browser --gui-address=127.0.0.1:${PORT} $FLAGS
+Restart=on-failure
+RestartSec=1min 30s
+
+[Install]
+WantedBy=default.target
+"#;
+
+    #[test]
+    fn it_parses_units() {
+        let unit = Unit::parse("syncthing.service", PathBuf::new(), UNIT, false).unwrap();
+
+        assert_eq!(unit.description, "Syncthing for syncthing.service");
+        assert!(unit.enabled);
+        assert_eq!(
+            unit.environment,
+            vec![
+                (String::from("HOME"), String::from("/home/user")),
+                (String::from("PORT"), String::from("8384")),
+            ]
+        );
+        assert_eq!(unit.restart, Restart::OnFailure);
+        assert_eq!(unit.restart_sec, Duration::from_secs(90));
+
+        let exec = &unit.exec_start[0];
+        assert!(exec.ignore_failure);
+        let env = HashMap::from([
+            (String::from("PORT"), String::from("8384")),
+            (String::from("FLAGS"), String::from("-a  -b")),
+        ]);
+        let (program, arg0, args) = exec.argv(&env).unwrap();
+        assert_eq!(program, "/bin/syncthing");
+        assert_eq!(arg0.as_deref(), Some("syncthing"));
+        assert_eq!(
+            args,
+            vec!["-no-browser", "--gui-address=127.0.0.1:8384", "-a", "-b"]
+        );
+    }
+
+    #[test]
+    fn it_splits_command_lines() {
+        let env = HashMap::from([(String::from("A"), String::from("x y"))]);
+
+        assert_eq!(
+            split_words(r#"echo "a b" 'c $A' d\ e "${A}" $$A"#, Some(&env)),
+            Some(vec![
+                String::from("echo"),
+                String::from("a b"),
+                String::from("c $A"),
+                String::from("d e"),
+                String::from("x y"),
+                String::from("$A"),
+            ])
+        );
+        assert_eq!(split_words("echo \"unterminated", None), None);
+    }
+
+    #[test]
+    fn it_rejects_unsupported_units() {
+        let parse = |text| Unit::parse("a.service", PathBuf::new(), text, false);
+
+        assert!(parse("[Service]\nType=forking\nExecStart=/bin/a\n").is_err());
+        assert!(parse("[Service]\nExecStart=/bin/a\nExecStart=/bin/b\n").is_err());
+        assert!(parse("[Service]\nType=oneshot\nExecStart=/bin/a\nExecStart=/bin/b\n").is_ok());
+        assert!(parse("[Unit]\nDescription=No service\n").is_err());
+        assert!(parse("[Service]\nExecStart=/bin/a\nRestart=sometimes\n").is_err());
+    }
+}
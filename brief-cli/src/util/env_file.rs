@@ -0,0 +1,64 @@
+/// Parse an env file: `KEY=VALUE` lines with `#` comments, an optional
+/// `export` prefix, and values optionally wrapped in single or double quotes.
+/// Returns the number of the first line that is not an assignment on error.
+pub fn parse_env_file(text: &str) -> Result<Vec<(String, String)>, usize> {
+    let mut vars = vec![];
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+
+        let Some((key, val)) = parse_assignment(line) else {
+            return Err(index + 1);
+        };
+        let val = val.trim();
+        let val = ['"', '\'']
+            .iter()
+            .find_map(|quote| val.strip_prefix(*quote)?.strip_suffix(*quote))
+            .unwrap_or(val);
+        vars.push((key.to_string(), val.to_string()));
+    }
+    Ok(vars)
+}
+
+/// Split `KEY=VALUE`, where `KEY` must be a valid variable name
+pub fn parse_assignment(text: &str) -> Option<(&str, &str)> {
+    let (key, val) = text.split_once('=')?;
+    let key = key.trim();
+    let valid = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    Some((key, val)).filter(|_| valid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_env_files() {
+        let text = "# comment\n\nA=1\nexport B = two words \nC=\"quoted\"\nD='single'\nE=\n";
+        let vars = parse_env_file(text).unwrap();
+
+        assert_eq!(
+            vars,
+            [
+                ("A", "1"),
+                ("B", "two words"),
+                ("C", "quoted"),
+                ("D", "single"),
+                ("E", ""),
+            ]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn it_reports_invalid_lines() {
+        assert_eq!(parse_env_file("A=1\nnot an assignment\n"), Err(2));
+        assert_eq!(parse_env_file("1A=1\n"), Err(1));
+    }
+}
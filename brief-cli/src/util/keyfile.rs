@@ -0,0 +1,207 @@
+use std::fs;
+use std::io::Result;
+use std::path::Path;
+
+/// Parser for the "key file" format shared by desktop entries, systemd units
+/// and brief's own configuration: `[Group]` headers followed by `Key=Value`
+/// lines, with `#` comments.
+#[derive(Clone, Debug, Default)]
+pub struct KeyFile {
+    groups: Vec<Group>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Group {
+    name: String,
+    entries: Vec<(String, String)>,
+}
+
+impl KeyFile {
+    pub fn parse(text: &str) -> Self {
+        let mut groups: Vec<Group> = vec![];
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+                groups.push(Group {
+                    name: name.to_string(),
+                    entries: vec![],
+                });
+                continue;
+            }
+
+            let (Some(group), Some((key, val))) = (groups.last_mut(), line.split_once('=')) else {
+                continue;
+            };
+            group
+                .entries
+                .push((key.trim().to_string(), val.trim().to_string()));
+        }
+
+        Self { groups }
+    }
+
+    pub fn parse_file(path: impl AsRef<Path>) -> Result<Self> {
+        fs::read_to_string(path).map(|text| Self::parse(&text))
+    }
+
+    /// Parse a systemd unit file, where a line ending in a backslash is
+    /// continued on the next one
+    pub fn parse_unit(text: &str) -> Self {
+        Self::parse(&text.replace("\\\n", " "))
+    }
+
+    pub fn groups(&self) -> impl Iterator<Item = &Group> {
+        self.groups.iter()
+    }
+
+    pub fn group(&self, name: &str) -> Option<&Group> {
+        self.groups.iter().find(|group| group.name == name)
+    }
+
+    pub fn get(&self, group: &str, key: &str) -> Option<&str> {
+        self.group(group)?.get(key)
+    }
+
+    pub fn get_bool(&self, group: &str, key: &str) -> Option<bool> {
+        self.group(group)?.get_bool(key)
+    }
+
+    pub fn get_list(&self, group: &str, key: &str) -> Vec<String> {
+        self.group(group)
+            .map(|group| group.get_list(key))
+            .unwrap_or_default()
+    }
+}
+
+impl Group {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// All entries in order, including repeated keys
+    pub fn entries(&self) -> &[(String, String)] {
+        &self.entries
+    }
+
+    /// Value of `key`. When a key is repeated the last value wins.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, val)| val.as_str())
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)? {
+            "true" | "yes" | "1" => Some(true),
+            "false" | "no" | "0" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Split a `;`-separated list, honouring `\;` escapes.
+    pub fn get_list(&self, key: &str) -> Vec<String> {
+        let Some(val) = self.get(key) else {
+            return vec![];
+        };
+
+        let mut list = vec![];
+        let mut item = String::new();
+        let mut chars = val.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(';') => item.push(';'),
+                    Some(x) => {
+                        item.push('\\');
+                        item.push(x);
+                    }
+                    None => item.push('\\'),
+                },
+                ';' => list.push(std::mem::take(&mut item)),
+                _ => item.push(c),
+            }
+        }
+        list.push(item);
+        list.retain(|item| !item.is_empty());
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESKTOP: &str = r#"
+# A comment
+[Desktop Entry]
+Name=Text Editor
+Name[nb]=Tekstbehandler
+Exec=gedit %U
+MimeType=text/plain;text/markdown;
+NoDisplay=false
+
+[Desktop Action new-window]
+Exec=gedit --new-window
+"#;
+
+    #[test]
+    fn it_parses_groups() {
+        let keyfile = KeyFile::parse(DESKTOP);
+
+        assert_eq!(keyfile.get("Desktop Entry", "Name"), Some("Text Editor"));
+        assert_eq!(
+            keyfile.get("Desktop Entry", "Name[nb]"),
+            Some("Tekstbehandler")
+        );
+        assert_eq!(keyfile.get("Desktop Entry", "Exec"), Some("gedit %U"));
+        assert_eq!(
+            keyfile.get("Desktop Action new-window", "Exec"),
+            Some("gedit --new-window")
+        );
+        assert_eq!(keyfile.get("Desktop Entry", "Missing"), None);
+        assert_eq!(keyfile.get("Missing", "Name"), None);
+    }
+
+    #[test]
+    fn it_parses_bools_and_lists() {
+        let keyfile = KeyFile::parse(DESKTOP);
+
+        assert_eq!(keyfile.get_bool("Desktop Entry", "NoDisplay"), Some(false));
+        assert_eq!(
+            keyfile.get_list("Desktop Entry", "MimeType"),
+            vec!["text/plain", "text/markdown"]
+        );
+        assert!(keyfile.get_list("Desktop Entry", "Categories").is_empty());
+    }
+
+    #[test]
+    fn it_uses_the_last_repeated_key() {
+        let keyfile = KeyFile::parse("[Export]\nBinDir=/a\nBinDir=/b\n");
+
+        assert_eq!(keyfile.get("Export", "BinDir"), Some("/b"));
+    }
+
+    #[test]
+    fn it_joins_continued_unit_lines() {
+        let keyfile = KeyFile::parse_unit("[Service]\nExecStart=/bin/foo \\\n  --bar\n");
+
+        assert_eq!(
+            keyfile.get("Service", "ExecStart"),
+            Some("/bin/foo    --bar")
+        );
+    }
+
+    #[test]
+    fn it_unescapes_list_separators() {
+        let keyfile = KeyFile::parse("[Export]\nApps=a\\;b;c\n");
+
+        assert_eq!(keyfile.get_list("Export", "Apps"), vec!["a;b", "c"]);
+    }
+}
@@ -1,5 +1,9 @@
+mod env_file;
+mod keyfile;
 mod mkdtemp;
 mod resolve_symlink;
 
+pub use env_file::{parse_assignment, parse_env_file};
+pub use keyfile::KeyFile;
 pub use mkdtemp::mkdtemp;
 pub use resolve_symlink::resolve_symlink;
@@ -0,0 +1,94 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+use std::thread::{self, sleep};
+use std::time::Duration;
+
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+
+use crate::config::Config;
+use crate::export;
+
+/// Watch the symlinks that make up the box's profiles and re-export
+/// applications and commands whenever one of them is switched.
+pub fn spawn(config: Config) {
+    thread::spawn(move || loop {
+        if let Err(err) = wait_for_change(&config) {
+            eprintln!("Could not watch nix profiles: {}", err);
+            return;
+        }
+
+        // Give nix a moment to finish switching all of its links
+        sleep(Duration::from_secs(1));
+        export::sync(&config, &export::default_bindir(&config));
+    });
+}
+
+fn wait_for_change(config: &Config) -> nix::Result<()> {
+    let inotify = Inotify::init(InitFlags::IN_CLOEXEC)?;
+
+    let links = profile_links(config);
+    let names: HashSet<OsString> = links
+        .iter()
+        .filter_map(|link| link.file_name())
+        .map(OsString::from)
+        .collect();
+    let dirs: HashSet<PathBuf> = links
+        .iter()
+        .filter_map(|link| link.parent())
+        .map(PathBuf::from)
+        .collect();
+
+    let flags = AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO | AddWatchFlags::IN_DELETE;
+    for dir in dirs {
+        if let Err(err) = inotify.add_watch(&dir, flags) {
+            eprintln!("Could not watch {}: {}", dir.display(), err);
+        }
+    }
+
+    loop {
+        let events = inotify.read_events()?;
+        if events
+            .iter()
+            .filter_map(|event| event.name.as_ref())
+            .any(|name| names.contains(name))
+        {
+            return Ok(());
+        }
+    }
+}
+
+/// Every symlink on the way from `~/.nix-profile` and `NIXBOX_ROOT` to the
+/// store, including the starting points themselves so that we notice when
+/// they are first created.
+fn profile_links(config: &Config) -> Vec<PathBuf> {
+    const MAX_LINKS: usize = 40;
+
+    let home = PathBuf::from(
+        config
+            .env
+            .get(&OsString::from("HOME"))
+            .cloned()
+            .or_else(|| std::env::var_os("HOME"))
+            .expect("Environment variable HOME not set"),
+    );
+    let roots = [home.join(".nix-profile"), config.nixbox_root().to_owned()];
+
+    let mut links = vec![];
+    for root in roots {
+        let mut path = root;
+        for _ in 0..MAX_LINKS {
+            links.push(path.clone());
+
+            let Ok(target) = fs::read_link(&path) else {
+                break;
+            };
+            path = match path.parent() {
+                Some(parent) if target.is_relative() => parent.join(target),
+                _ => target,
+            };
+        }
+    }
+    links
+}